@@ -0,0 +1,668 @@
+use crate::core::distance::{AngleProduct, DistanceMetric};
+use crate::core::environment::Environment;
+use crate::core::integrator::{DormandPrince45, RungeKutta4, SemiImplicitEuler};
+use crate::core::util::Point;
+use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Up to this many links are handled without allocating, which covers the double pendulum
+const INLINE_LINKS: usize = 4;
+
+/// A pendulum with any number of links, each hanging from the bob of the previous one.
+/// Link `i` is described by the `i`th [`Pendulum`] and [`PendulumConfiguration`].
+/// The equations of motion are those of [`DoublePendulumConfiguration`] too, which is the two link case.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ChainPendulumConfiguration {
+    links: Vec<PendulumConfiguration>,
+    /// Step size \[time\] an adaptive integrator ended its last step with, it picks up from there next time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    adaptive_step: Option<f64>,
+}
+
+impl ChainPendulumConfiguration {
+    pub fn new(links: Vec<PendulumConfiguration>) -> Self {
+        ChainPendulumConfiguration {
+            links,
+            adaptive_step: None,
+        }
+    }
+
+    pub fn links(&self) -> &Vec<PendulumConfiguration> {
+        &self.links
+    }
+
+    /// Positions of all bobs, starting with the one closest to the pivot
+    pub fn positions(&self, pendulums: &[Pendulum]) -> Vec<Point> {
+        link_positions(&self.links, pendulums).collect()
+    }
+
+    /// 0 is exactly identical, 1 is theoretical maximum distance.
+    /// Like [`DoublePendulumConfiguration::distance`], this is the product of the normalized angle differences.
+    pub fn distance(&self, other: &ChainPendulumConfiguration) -> f64 {
+        AngleProduct.distance(&self.links, &[], &other.links, &[])
+    }
+
+    /// Generalized forces of everything besides gravity, see [`DoublePendulumConfiguration::generalized_forces`].
+    /// The driving torques of the environment act at the first two joints.
+    pub fn generalized_forces(
+        &self,
+        pendulums: &[Pendulum],
+        environment: &Environment,
+        time: f64,
+    ) -> Vec<f64> {
+        let mut forces = vec![0.0; self.links.len()];
+        link_generalized_forces(&self.links, pendulums, environment, time, &mut forces);
+        forces
+    }
+
+    /// Angular acceleration of every link, see [`link_angular_accelerations`]
+    pub fn angular_accelerations(
+        &self,
+        pendulums: &[Pendulum],
+        environment: &Environment,
+        time: f64,
+    ) -> Vec<f64> {
+        let mut accelerations = vec![0.0; self.links.len()];
+        link_angular_accelerations(
+            &self.links,
+            pendulums,
+            environment,
+            time,
+            &mut accelerations,
+        );
+        accelerations
+    }
+
+    /// `[angles..., angular_velocities...]`
+    fn state(&self) -> Vec<f64> {
+        self.links
+            .iter()
+            .map(|link| link.angle)
+            .chain(self.links.iter().map(|link| link.angular_velocity))
+            .collect()
+    }
+
+    /// This configuration with the angles and angular velocities of `state`, see [`state`](Self::state)
+    fn with_state(&self, state: &[f64]) -> Self {
+        let n = self.links.len();
+
+        ChainPendulumConfiguration {
+            links: self
+                .links
+                .iter()
+                .enumerate()
+                .map(|(i, link)| PendulumConfiguration {
+                    angle: state[i],
+                    angular_velocity: state[n + i],
+                    ..*link
+                })
+                .collect(),
+            adaptive_step: self.adaptive_step,
+        }
+    }
+
+    /// Time derivative of `[angles..., angular_velocities...]`
+    fn derivative(&self, pendulums: &[Pendulum], environment: &Environment, time: f64) -> Vec<f64> {
+        self.links
+            .iter()
+            .map(|link| link.angular_velocity)
+            .chain(self.angular_accelerations(pendulums, environment, time))
+            .collect()
+    }
+
+    /// Moves this configuration along `derivative` for `h` \[time\], without normalizing the angles
+    fn offset(&self, derivative: &[f64], h: f64) -> Self {
+        let state: Vec<_> = self
+            .state()
+            .iter()
+            .zip(derivative)
+            .map(|(value, change)| value + change * h)
+            .collect();
+
+        self.with_state(&state)
+    }
+
+    fn wrap_angles(&mut self) {
+        for link in &mut self.links {
+            link.wrap_angle();
+        }
+    }
+
+    /// Steps from `time` by `duration` scaled with the environment's time scale
+    pub fn step(
+        &mut self,
+        integrator: &impl ChainIntegrator,
+        pendulums: &[Pendulum],
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        integrator.step_chain(
+            self,
+            pendulums,
+            environment,
+            time,
+            environment.scale(duration),
+        );
+    }
+}
+
+impl From<DoublePendulumConfiguration> for ChainPendulumConfiguration {
+    fn from(configuration: DoublePendulumConfiguration) -> Self {
+        ChainPendulumConfiguration::new(vec![configuration.a, configuration.b])
+    }
+}
+
+/// Positions of the bobs of `links`, starting with the one closest to the pivot
+pub(crate) fn link_positions<'a>(
+    links: &'a [PendulumConfiguration],
+    pendulums: &'a [Pendulum],
+) -> impl Iterator<Item = Point> + 'a {
+    links
+        .iter()
+        .zip(pendulums)
+        .scan(Point { x: 0.0, y: 0.0 }, |position, (link, pendulum)| {
+            *position = *position
+                + Point {
+                    x: pendulum.length * link.angle.sin(),
+                    y: -pendulum.length * link.angle.cos(),
+                };
+            Some(*position)
+        })
+}
+
+/// Writes the generalized forces (torques on the angles) of everything besides gravity on `links` into `forces`,
+/// i.e. damping, driving torques at the first two joints and the inertial forces of an accelerating pivot
+pub(crate) fn link_generalized_forces(
+    links: &[PendulumConfiguration],
+    pendulums: &[Pendulum],
+    environment: &Environment,
+    time: f64,
+    forces: &mut [f64],
+) {
+    let driving = environment.driving();
+    forces.fill(0.0);
+
+    if driving.is_undriven()
+        && pendulums
+            .iter()
+            .all(|pendulum| pendulum.damping.is_frictionless())
+    {
+        return;
+    }
+
+    let pivot_acceleration_x = driving.pivot_acceleration_x().value(time);
+    let pivot_acceleration_y = driving.pivot_acceleration_y().value(time);
+
+    let origin = Point { x: 0.0, y: 0.0 };
+    let (mut inline_bob_forces, mut heap_bob_forces) = ([origin; INLINE_LINKS], Vec::new());
+    let (mut inline_tangents, mut heap_tangents) = ([origin; INLINE_LINKS], Vec::new());
+    // Forces on every bob and the tangents d(position)/d(angle) of every link
+    let bob_forces = scratch(
+        &mut inline_bob_forces,
+        &mut heap_bob_forces,
+        links.len(),
+        origin,
+    );
+    let tangents = scratch(
+        &mut inline_tangents,
+        &mut heap_tangents,
+        links.len(),
+        origin,
+    );
+
+    let mut previous_ang_vel = 0.0;
+    let mut velocity = origin;
+
+    for (i, (link, pendulum)) in links.iter().zip(pendulums).enumerate() {
+        let driving_torque = match i {
+            0 => driving.torque_a().value(time),
+            1 => driving.torque_b().value(time),
+            _ => 0.0,
+        };
+        // Joints act between two links, pushing them in opposite directions
+        let torque = pendulum
+            .damping
+            .joint_torque(link.angular_velocity - previous_ang_vel)
+            + driving_torque;
+        forces[i] += torque;
+        if i > 0 {
+            forces[i - 1] -= torque;
+        }
+        previous_ang_vel = link.angular_velocity;
+
+        let tangent = Point {
+            x: pendulum.length * link.angle.cos(),
+            y: pendulum.length * link.angle.sin(),
+        };
+        velocity = velocity
+            + Point {
+                x: tangent.x * link.angular_velocity,
+                y: tangent.y * link.angular_velocity,
+            };
+        let (drag_x, drag_y) = pendulum.damping.drag_force(velocity.x, velocity.y);
+
+        // In the frame of the pivot, its acceleration looks like an extra force of -mass * acceleration on every bob
+        bob_forces[i] = Point {
+            x: drag_x - pendulum.mass * pivot_acceleration_x,
+            y: drag_y - pendulum.mass * pivot_acceleration_y,
+        };
+        tangents[i] = tangent;
+    }
+
+    // A force on a bob acts on every link between it and the pivot
+    let mut tail_force = origin;
+    for i in (0..links.len()).rev() {
+        tail_force = tail_force + bob_forces[i];
+        forces[i] += tail_force.x * tangents[i].x + tail_force.y * tangents[i].y;
+    }
+}
+
+/// Writes the angular accelerations of `links` into `accelerations` by solving the mass matrix system of the chain Lagrangian,
+/// `sum_j M_ij * acc_j = -sum_j μ_ij l_i l_j sin(θ_i - θ_j) ω_j^2 - μ_i g l_i sin(θ_i - φ) + Q_i`
+/// with `M_ij = μ_ij l_i l_j cos(θ_i - θ_j)`, μ_ij the mass hanging from both link i and link j
+/// and φ the direction of gravity.
+pub(crate) fn link_angular_accelerations(
+    links: &[PendulumConfiguration],
+    pendulums: &[Pendulum],
+    environment: &Environment,
+    time: f64,
+    accelerations: &mut [f64],
+) {
+    let n = links.len();
+    let gravity = environment.gravity();
+    let gravity_direction = environment.gravity_direction();
+
+    // tail_masses[i] is the mass hanging from link i, including its own bob
+    let (mut inline_tail_masses, mut heap_tail_masses) = ([0.0; INLINE_LINKS], Vec::new());
+    let tail_masses = scratch(&mut inline_tail_masses, &mut heap_tail_masses, n, 0.0);
+    let mut tail_mass = 0.0;
+    for i in (0..n).rev() {
+        tail_mass += pendulums[i].mass;
+        tail_masses[i] = tail_mass;
+    }
+
+    let (mut inline_matrix, mut heap_matrix) = ([0.0; INLINE_LINKS * INLINE_LINKS], Vec::new());
+    let matrix = scratch(&mut inline_matrix, &mut heap_matrix, n * n, 0.0);
+    // The right hand side, solved for the accelerations in place
+    let rhs = accelerations;
+    link_generalized_forces(links, pendulums, environment, time, rhs);
+
+    for i in 0..n {
+        let angle_i = links[i].angle;
+        let len_i = pendulums[i].length;
+
+        for j in 0..n {
+            let angle_diff = angle_i - links[j].angle;
+            let ang_vel_j = links[j].angular_velocity;
+            let coupling = tail_masses[usize::max(i, j)] * len_i * pendulums[j].length;
+
+            matrix[i * n + j] = coupling * angle_diff.cos();
+            rhs[i] -= coupling * angle_diff.sin() * ang_vel_j * ang_vel_j;
+        }
+
+        rhs[i] -= tail_masses[i] * gravity * len_i * f64::sin(angle_i - gravity_direction);
+    }
+
+    solve_linear_system(matrix, rhs);
+}
+
+/// The first `len` elements of `inline` if they fit, or of `heap` otherwise, all set to `fill`
+fn scratch<'a, T: Copy, const N: usize>(
+    inline: &'a mut [T; N],
+    heap: &'a mut Vec<T>,
+    len: usize,
+    fill: T,
+) -> &'a mut [T] {
+    if len <= N {
+        let slice = &mut inline[..len];
+        slice.fill(fill);
+        slice
+    } else {
+        heap.clear();
+        heap.resize(len, fill);
+        heap
+    }
+}
+
+/// Gaussian elimination with partial pivoting. `matrix` is square and invertible, stored row by row,
+/// and gets overwritten. The solution ends up in `rhs`.
+fn solve_linear_system(matrix: &mut [f64], rhs: &mut [f64]) {
+    let n = rhs.len();
+
+    for column in 0..n {
+        let pivot = (column..n)
+            .max_by(|&i, &j| {
+                matrix[i * n + column]
+                    .abs()
+                    .total_cmp(&matrix[j * n + column].abs())
+            })
+            .expect("column is in range");
+        if pivot != column {
+            for k in 0..n {
+                matrix.swap(column * n + k, pivot * n + k);
+            }
+            rhs.swap(column, pivot);
+        }
+
+        let (pivot_rows, rows) = matrix.split_at_mut((column + 1) * n);
+        let pivot_row = &pivot_rows[column * n..];
+        for (offset, row) in rows.chunks_exact_mut(n).enumerate() {
+            let factor = row[column] / pivot_row[column];
+            for (entry, pivot_entry) in row.iter_mut().zip(pivot_row).skip(column) {
+                *entry -= factor * pivot_entry;
+            }
+            rhs[column + 1 + offset] -= factor * rhs[column];
+        }
+    }
+
+    for row in (0..n).rev() {
+        let known: f64 = (row + 1..n).map(|k| matrix[row * n + k] * rhs[k]).sum();
+        rhs[row] = (rhs[row] - known) / matrix[row * n + row];
+    }
+}
+
+/// An [`Integrator`](crate::core::integrator::Integrator) that also knows how to step chains.
+/// The symplectic integrators work on the Hamiltonian of the double pendulum only, so they don't.
+pub trait ChainIntegrator {
+    fn step_chain(
+        &self,
+        configuration: &mut ChainPendulumConfiguration,
+        pendulums: &[Pendulum],
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    );
+}
+
+impl ChainIntegrator for SemiImplicitEuler {
+    fn step_chain(
+        &self,
+        configuration: &mut ChainPendulumConfiguration,
+        pendulums: &[Pendulum],
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        let ang_accs = configuration.angular_accelerations(pendulums, environment, time);
+        let secs = duration.as_secs_f64();
+
+        for (link, ang_acc) in configuration.links.iter_mut().zip(ang_accs) {
+            link.angular_velocity += ang_acc * secs;
+            link.angle += link.angular_velocity * secs;
+        }
+
+        configuration.wrap_angles();
+    }
+}
+
+impl ChainIntegrator for RungeKutta4 {
+    fn step_chain(
+        &self,
+        configuration: &mut ChainPendulumConfiguration,
+        pendulums: &[Pendulum],
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        let h = duration.as_secs_f64();
+
+        let k1 = configuration.derivative(pendulums, environment, time);
+        let k2 =
+            configuration
+                .offset(&k1, h / 2.0)
+                .derivative(pendulums, environment, time + h / 2.0);
+        let k3 =
+            configuration
+                .offset(&k2, h / 2.0)
+                .derivative(pendulums, environment, time + h / 2.0);
+        let k4 = configuration
+            .offset(&k3, h)
+            .derivative(pendulums, environment, time + h);
+
+        let weighted: Vec<_> = (0..k1.len())
+            .map(|i| (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i]) / 6.0)
+            .collect();
+
+        *configuration = configuration.offset(&weighted, h);
+        configuration.wrap_angles();
+    }
+}
+
+impl ChainIntegrator for DormandPrince45 {
+    fn step_chain(
+        &self,
+        configuration: &mut ChainPendulumConfiguration,
+        pendulums: &[Pendulum],
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        let start = configuration.clone();
+        let mut state = configuration.state();
+
+        self.integrate(
+            &mut state,
+            &mut configuration.adaptive_step,
+            time,
+            duration.as_secs_f64(),
+            |state, time, derivative| {
+                derivative.copy_from_slice(&start.with_state(state).derivative(
+                    pendulums,
+                    environment,
+                    time,
+                ))
+            },
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+
+        *configuration = configuration.with_state(&state);
+        configuration.wrap_angles();
+    }
+}
+
+/// Like [`DoublePendulumCollection`](crate::core::DoublePendulumCollection), but for chains.
+/// All configurations need to have as many links as there are pendulums.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ChainPendulumCollection {
+    pendulums: Vec<Pendulum>,
+    #[serde(default)]
+    environment: Environment,
+    #[serde(default)]
+    time: f64,
+    pendulum_configurations: Vec<ChainPendulumConfiguration>,
+}
+
+impl ChainPendulumCollection {
+    /// # Panics
+    /// If any configuration doesn't have exactly one link per pendulum
+    pub fn new(
+        pendulums: Vec<Pendulum>,
+        pendulum_configurations: Vec<ChainPendulumConfiguration>,
+    ) -> Self {
+        assert!(
+            pendulum_configurations
+                .iter()
+                .all(|configuration| configuration.links.len() == pendulums.len()),
+            "every configuration needs one link per pendulum"
+        );
+
+        ChainPendulumCollection {
+            pendulums,
+            environment: Environment::default(),
+            time: 0.0,
+            pendulum_configurations,
+        }
+    }
+
+    pub fn with_environment(mut self, environment: Environment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    pub fn pendulums(&self) -> &Vec<Pendulum> {
+        &self.pendulums
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn pendulum_configurations(&self) -> &Vec<ChainPendulumConfiguration> {
+        &self.pendulum_configurations
+    }
+
+    pub fn step_all(&mut self, integrator: &(impl ChainIntegrator + Sync), step_time: Duration) {
+        self.step_all_n_times(integrator, step_time, 1);
+    }
+
+    pub fn step_all_n_times(
+        &mut self,
+        integrator: &(impl ChainIntegrator + Sync),
+        step_time: Duration,
+        n: u32,
+    ) {
+        let pendulums = &self.pendulums;
+        let environment = &self.environment;
+        let time = self.time;
+        let scaled_step_time = environment.scale(step_time).as_secs_f64();
+
+        self.pendulum_configurations
+            .par_iter_mut()
+            .for_each(|pendulum| {
+                (0..n).for_each(|i| {
+                    pendulum.step(
+                        integrator,
+                        pendulums,
+                        environment,
+                        time + i as f64 * scaled_step_time,
+                        step_time,
+                    )
+                })
+            });
+
+        self.time += n as f64 * scaled_step_time;
+    }
+}
+
+#[test]
+fn test_two_links_match_double_pendulum() {
+    use crate::core::damping::Damping;
+    use crate::core::driving::{Driving, Signal};
+    use crate::core::hamiltonian::{mass_matrix, solve_linear};
+
+    let pendulum_a = Pendulum::new(180.0, 10.0);
+    let pendulum_b = Pendulum::new(162.0, 1.0);
+    let configuration = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.0, -0.7),
+        PendulumConfiguration::new(-1.0, 1.3),
+    );
+    let chain = ChainPendulumConfiguration::from(configuration);
+    let close =
+        |value: f64, expected: f64| (value - expected).abs() < 1e-9 * expected.abs().max(1.0);
+
+    // The textbook equations of motion of the undamped double pendulum with gravity straight down
+    let closed_form = |environment: &Environment| {
+        let gravity = environment.gravity();
+        let (mass_a, mass_b) = (pendulum_a.mass, pendulum_b.mass);
+        let (len_a, len_b) = (pendulum_a.length, pendulum_b.length);
+        let angle_a = configuration.a.angle - environment.gravity_direction();
+        let angle_b = configuration.b.angle - environment.gravity_direction();
+        let (ang_vel_a, ang_vel_b) = (
+            configuration.a.angular_velocity,
+            configuration.b.angular_velocity,
+        );
+        let angle_diff = angle_a - angle_b;
+        let denominator = 2.0 * mass_a + mass_b - mass_b * f64::cos(2.0 * angle_diff);
+
+        (
+            (-gravity * (2.0 * mass_a + mass_b) * angle_a.sin()
+                - mass_b * gravity * f64::sin(angle_a - 2.0 * angle_b)
+                - 2.0
+                    * angle_diff.sin()
+                    * mass_b
+                    * (ang_vel_b * ang_vel_b * len_b
+                        + ang_vel_a * ang_vel_a * len_a * angle_diff.cos()))
+                / (len_a * denominator),
+            2.0 * angle_diff.sin()
+                * (ang_vel_a * ang_vel_a * len_a * (mass_a + mass_b)
+                    + gravity * (mass_a + mass_b) * angle_a.cos()
+                    + ang_vel_b * ang_vel_b * len_b * mass_b * angle_diff.cos())
+                / (len_b * denominator),
+        )
+    };
+
+    let environment = Environment::new(100.0, 0.2, 1.0);
+    let (expected_a, expected_b) = closed_form(&environment);
+    let (ang_acc_a, ang_acc_b) =
+        configuration.angular_accelerations(&pendulum_a, &pendulum_b, &environment, 0.7);
+    let chain_ang_accs = chain.angular_accelerations(&[pendulum_a, pendulum_b], &environment, 0.7);
+
+    assert!(close(ang_acc_a, expected_a), "{} {}", ang_acc_a, expected_a);
+    assert!(close(ang_acc_b, expected_b), "{} {}", ang_acc_b, expected_b);
+    assert_eq!(chain_ang_accs, vec![ang_acc_a, ang_acc_b]);
+
+    // Everything besides gravity comes on top through the mass matrix
+    let pendulum_a = pendulum_a.with_damping(Damping::new(0.3, 0.1, 0.01));
+    let pendulum_b = pendulum_b.with_damping(Damping::new(0.2, 0.0, 0.02));
+    let environment = environment.with_driving(Driving::new(
+        Signal::sinusoidal(5.0, 2.0, 0.0),
+        Signal::Constant(-3.0),
+        Signal::Constant(1.0),
+        Signal::sinusoidal(2.0, 1.0, 0.5),
+    ));
+    let forces = configuration.generalized_forces(&pendulum_a, &pendulum_b, &environment, 0.7);
+    let (force_acc_a, force_acc_b) = solve_linear(
+        mass_matrix(
+            configuration.a.angle,
+            configuration.b.angle,
+            &pendulum_a,
+            &pendulum_b,
+        ),
+        forces,
+    );
+    let (ang_acc_a, ang_acc_b) =
+        configuration.angular_accelerations(&pendulum_a, &pendulum_b, &environment, 0.7);
+    assert!(close(ang_acc_a, expected_a + force_acc_a), "{}", ang_acc_a);
+    assert!(close(ang_acc_b, expected_b + force_acc_b), "{}", ang_acc_b);
+    assert_eq!(
+        chain.generalized_forces(&[pendulum_a, pendulum_b], &environment, 0.7),
+        vec![forces.0, forces.1]
+    );
+
+    let (a_position, b_position) = configuration.positions(&pendulum_a, &pendulum_b);
+    assert_eq!(
+        chain.positions(&[pendulum_a, pendulum_b]),
+        vec![a_position, b_position]
+    );
+
+    // Stepping a two link chain is stepping a double pendulum, adaptive integrator included
+    let mut double = configuration;
+    let mut chain = chain;
+    for i in 0..10 {
+        let time = i as f64 * 0.05;
+        let duration = Duration::from_secs_f64(0.05);
+        double.step(
+            &DormandPrince45::default(),
+            &pendulum_a,
+            &pendulum_b,
+            &environment,
+            time,
+            duration,
+        );
+        chain.step(
+            &DormandPrince45::default(),
+            &[pendulum_a, pendulum_b],
+            &environment,
+            time,
+            duration,
+        );
+    }
+    assert_eq!(chain.links(), &vec![double.a, double.b]);
+}
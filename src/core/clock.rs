@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+/// Simulated time and steps taken. Steps are added with compensated (Kahan) summation,
+/// so long runs of tiny steps don't lose time to rounding.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct SimulationClock {
+    /// Simulated \[time\] since the start
+    #[serde(default)]
+    time: f64,
+    /// Rounding error of `time` that is carried over into the next addition
+    #[serde(default, rename = "time_compensation")]
+    compensation: f64,
+    /// Number of integrator steps taken
+    #[serde(default)]
+    steps: u64,
+}
+
+impl SimulationClock {
+    pub fn new() -> Self {
+        SimulationClock::default()
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Moves the clock on by `n` steps of `step_time` \[time\] each
+    pub fn advance(&mut self, step_time: f64, n: u32) {
+        let addition = n as f64 * step_time - self.compensation;
+        let time = self.time + addition;
+
+        self.compensation = (time - self.time) - addition;
+        self.time = time;
+        self.steps += n as u64;
+    }
+}
+
+#[test]
+fn test_compensated_summation() {
+    let mut clock = SimulationClock::new();
+    let mut naive = 0.0;
+
+    for _ in 0..10_000_000 {
+        clock.advance(0.0001, 1);
+        naive += 0.0001;
+    }
+
+    assert_eq!(clock.steps(), 10_000_000);
+    assert!((clock.time() - 1000.0).abs() < 1e-9, "{}", clock.time());
+    assert!((naive - 1000.0f64).abs() > 1e-9, "{}", naive);
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+
+/// Energy losses of one arm. The joint terms act at the joint the arm hangs from
+/// (the pivot for the first arm, the first bob for the second arm) against the
+/// relative angular velocity there, air drag acts on the arm's bob.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Damping {
+    /// Viscous joint damping, torque per (radians per \[time\])
+    viscous: f64,
+    /// Coulomb (dry) joint friction, constant torque against the direction of motion.
+    /// There is no sticking, a resting joint just doesn't get any friction.
+    coulomb: f64,
+    /// Quadratic air drag on the bob, force per (length per \[time\])^2
+    air_drag: f64,
+}
+
+impl Damping {
+    pub fn new(viscous: f64, coulomb: f64, air_drag: f64) -> Self {
+        Damping {
+            viscous,
+            coulomb,
+            air_drag,
+        }
+    }
+
+    pub fn frictionless() -> Self {
+        Damping::default()
+    }
+
+    pub fn viscous(&self) -> f64 {
+        self.viscous
+    }
+
+    pub fn coulomb(&self) -> f64 {
+        self.coulomb
+    }
+
+    pub fn air_drag(&self) -> f64 {
+        self.air_drag
+    }
+
+    pub fn is_frictionless(&self) -> bool {
+        self.viscous == 0.0 && self.coulomb == 0.0 && self.air_drag == 0.0
+    }
+
+    /// Torque on the arm from the joint it hangs from, given the relative angular velocity at that joint
+    pub fn joint_torque(&self, relative_angular_velocity: f64) -> f64 {
+        let direction = if relative_angular_velocity == 0.0 {
+            0.0
+        } else {
+            relative_angular_velocity.signum()
+        };
+
+        -self.viscous * relative_angular_velocity - self.coulomb * direction
+    }
+
+    /// Drag force on the bob given its velocity
+    pub fn drag_force(&self, velocity_x: f64, velocity_y: f64) -> (f64, f64) {
+        let speed = f64::hypot(velocity_x, velocity_y);
+
+        (
+            -self.air_drag * speed * velocity_x,
+            -self.air_drag * speed * velocity_y,
+        )
+    }
+}
+
+#[test]
+fn test_damping_dissipates() {
+    use crate::core::environment::Environment;
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+    use std::time::Duration;
+
+    let environment = Environment::default();
+    let initial = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.0, 0.0),
+        PendulumConfiguration::new(-1.0, 3.0),
+    );
+
+    for damping in [
+        Damping::new(0.5, 0.0, 0.0),
+        Damping::new(0.0, 0.5, 0.0),
+        Damping::new(0.0, 0.0, 0.05),
+    ] {
+        let pendulum_a = Pendulum::new(1.0, 2.0).with_damping(damping);
+        let pendulum_b = Pendulum::new(1.5, 1.0).with_damping(damping);
+
+        let mut configuration = initial;
+        let mut energy = configuration.total_energy(&pendulum_a, &pendulum_b, &environment);
+        for _ in 0..2_000 {
+            configuration.step(
+                &RungeKutta4,
+                &pendulum_a,
+                &pendulum_b,
+                &environment,
+                0.0,
+                Duration::from_secs_f64(0.0005),
+            );
+            let new_energy = configuration.total_energy(&pendulum_a, &pendulum_b, &environment);
+            assert!(
+                new_energy <= energy + 1e-9,
+                "{:?}: {} > {}",
+                damping,
+                new_energy,
+                energy
+            );
+            energy = new_energy;
+        }
+
+        let initial_energy = initial.total_energy(&pendulum_a, &pendulum_b, &environment);
+        assert!(energy < initial_energy - 1.0, "{:?}", damping);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::core::util::{normalize_angle, Point};
+use crate::core::{Pendulum, PendulumConfiguration};
+use std::f64::consts::PI;
+
+/// How different two pendulums are, each given as its links from the pivot outwards.
+/// Works for double pendulums and chains alike, but both pendulums need the same number of links.
+pub trait DistanceMetric: Sync {
+    /// 0 is exactly identical, 1 is theoretical maximum distance
+    fn distance(
+        &self,
+        first: &[PendulumConfiguration],
+        first_pendulums: &[Pendulum],
+        second: &[PendulumConfiguration],
+        second_pendulums: &[Pendulum],
+    ) -> f64;
+}
+
+/// Product of the normalized angle differences, what [`DoublePendulumConfiguration::distance`](crate::core::DoublePendulumConfiguration::distance) uses.
+/// Zero as soon as any one angle matches.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct AngleProduct;
+
+impl DistanceMetric for AngleProduct {
+    fn distance(
+        &self,
+        first: &[PendulumConfiguration],
+        _first_pendulums: &[Pendulum],
+        second: &[PendulumConfiguration],
+        _second_pendulums: &[Pendulum],
+    ) -> f64 {
+        angle_differences(first, second).product()
+    }
+}
+
+/// Euclidean distance of the angles on the torus, the short way around for every angle
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct TorusEuclidean;
+
+impl DistanceMetric for TorusEuclidean {
+    fn distance(
+        &self,
+        first: &[PendulumConfiguration],
+        _first_pendulums: &[Pendulum],
+        second: &[PendulumConfiguration],
+        _second_pendulums: &[Pendulum],
+    ) -> f64 {
+        root_mean_square(angle_differences(first, second))
+    }
+}
+
+/// Euclidean distance in phase space, angles like [`TorusEuclidean`]
+/// and angular velocities relative to `velocity_scale`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PhaseSpace {
+    /// Radians per \[time\]. Angular velocity differences this large or larger count as maximally different.
+    velocity_scale: f64,
+}
+
+impl PhaseSpace {
+    pub fn new(velocity_scale: f64) -> Self {
+        PhaseSpace { velocity_scale }
+    }
+
+    pub fn velocity_scale(&self) -> f64 {
+        self.velocity_scale
+    }
+}
+
+impl DistanceMetric for PhaseSpace {
+    fn distance(
+        &self,
+        first: &[PendulumConfiguration],
+        _first_pendulums: &[Pendulum],
+        second: &[PendulumConfiguration],
+        _second_pendulums: &[Pendulum],
+    ) -> f64 {
+        let velocity_differences = first.iter().zip(second).map(|(link, other_link)| {
+            f64::min(
+                (link.angular_velocity - other_link.angular_velocity).abs() / self.velocity_scale,
+                1.0,
+            )
+        });
+
+        root_mean_square(angle_differences(first, second).chain(velocity_differences))
+    }
+}
+
+/// Cartesian distance between the outermost bobs, relative to both pendulums fully extended in opposite directions
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct BobDistance;
+
+impl DistanceMetric for BobDistance {
+    fn distance(
+        &self,
+        first: &[PendulumConfiguration],
+        first_pendulums: &[Pendulum],
+        second: &[PendulumConfiguration],
+        second_pendulums: &[Pendulum],
+    ) -> f64 {
+        let first_bob = outermost_bob(first, first_pendulums);
+        let second_bob = outermost_bob(second, second_pendulums);
+        let max_distance = first_pendulums
+            .iter()
+            .chain(second_pendulums)
+            .map(|pendulum| pendulum.length())
+            .sum::<f64>();
+
+        f64::hypot(first_bob.x - second_bob.x, first_bob.y - second_bob.y) / max_distance
+    }
+}
+
+/// Normalized angle differences, between 0 and 1
+fn angle_differences<'a>(
+    first: &'a [PendulumConfiguration],
+    second: &'a [PendulumConfiguration],
+) -> impl Iterator<Item = f64> + 'a {
+    assert_eq!(first.len(), second.len(), "link counts differ");
+
+    first
+        .iter()
+        .zip(second)
+        .map(|(link, other_link)| f64::abs(normalize_angle(link.angle - other_link.angle)) / PI)
+}
+
+/// Between 0 and 1 if every value is
+fn root_mean_square(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| {
+        (sum + value * value, count + 1)
+    });
+
+    if count == 0 {
+        0.0
+    } else {
+        f64::sqrt(sum / count as f64)
+    }
+}
+
+fn outermost_bob(links: &[PendulumConfiguration], pendulums: &[Pendulum]) -> Point {
+    links
+        .iter()
+        .zip(pendulums)
+        .fold(Point { x: 0.0, y: 0.0 }, |position, (link, pendulum)| {
+            position
+                + Point {
+                    x: pendulum.length() * link.angle.sin(),
+                    y: -pendulum.length() * link.angle.cos(),
+                }
+        })
+}
+
+#[test]
+fn test_distance_metrics() {
+    let pendulums = [Pendulum::new(1.0, 1.0), Pendulum::new(1.0, 1.0)];
+    let hanging = [
+        PendulumConfiguration::new(0.0, 0.0),
+        PendulumConfiguration::new(0.0, 0.0),
+    ];
+    let first_arm_moved = [
+        PendulumConfiguration::new(PI / 2.0, 0.0),
+        PendulumConfiguration::new(0.0, 0.0),
+    ];
+    let spinning = [
+        PendulumConfiguration::new(0.0, 10.0),
+        PendulumConfiguration::new(0.0, -10.0),
+    ];
+    let upside_down = [
+        PendulumConfiguration::new(PI, 0.0),
+        PendulumConfiguration::new(PI, 0.0),
+    ];
+
+    let distances = |metric: &dyn DistanceMetric| {
+        [first_arm_moved, spinning, upside_down]
+            .map(|other| metric.distance(&hanging, &pendulums, &other, &pendulums))
+    };
+
+    // The product misses that the first arm moved, and the velocities
+    assert_eq!(distances(&AngleProduct), [0.0, 0.0, 1.0]);
+    assert_eq!(distances(&TorusEuclidean), [f64::sqrt(0.125), 0.0, 1.0]);
+    assert_eq!(
+        distances(&PhaseSpace::new(5.0)),
+        [0.25, f64::sqrt(0.5), f64::sqrt(0.5)]
+    );
+
+    let [moved, spun, flipped] = distances(&BobDistance);
+    assert!((moved - f64::sqrt(2.0) / 4.0).abs() < 1e-12, "{}", moved);
+    assert_eq!(spun, 0.0);
+    assert!((flipped - 1.0).abs() < 1e-12, "{}", flipped);
+}
@@ -0,0 +1,187 @@
+use crate::core::distance::DistanceMetric;
+use crate::core::DoublePendulumCollection;
+use std::io::Write;
+
+/// Follows how neighbouring members of a collection, like those of a sweep, come apart over time.
+/// Distances are only looked at when [`record`](Self::record) is called, so divergence times are
+/// only as precise as the recording interval.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DivergenceTracker<M> {
+    metric: M,
+    /// Distance by `metric` from which on a pair counts as diverged
+    threshold: f64,
+    /// For every pair of members `(i, i + 1)`, the first recorded time their distance passed the threshold
+    divergence_times: Vec<Option<f64>>,
+    /// Collection time of every recording
+    times: Vec<f64>,
+    /// Distance of every pair at every recording
+    distances: Vec<Vec<f64>>,
+}
+
+impl<M: DistanceMetric> DivergenceTracker<M> {
+    pub fn new(metric: M, threshold: f64, collection: &DoublePendulumCollection) -> Self {
+        let pairs = collection.pendulum_configurations().len().saturating_sub(1);
+
+        DivergenceTracker {
+            metric,
+            threshold,
+            divergence_times: vec![None; pairs],
+            times: Vec::new(),
+            distances: Vec::new(),
+        }
+    }
+
+    pub fn metric(&self) -> &M {
+        &self.metric
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub fn divergence_times(&self) -> &[Option<f64>] {
+        &self.divergence_times
+    }
+
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    /// One row per recording, one column per pair of neighbours
+    pub fn distances(&self) -> &[Vec<f64>] {
+        &self.distances
+    }
+
+    /// How many pairs have diverged by now
+    pub fn diverged_count(&self) -> usize {
+        self.divergence_times
+            .iter()
+            .filter(|time| time.is_some())
+            .count()
+    }
+
+    /// Adds the current neighbour distances of `collection` to the profile
+    ///
+    /// # Panics
+    /// If the collection gained members the tracker wasn't told about with [`insert_members`](Self::insert_members)
+    pub fn record(&mut self, collection: &DoublePendulumCollection) {
+        let time = collection.time();
+        let distances = collection.neighbour_distances(&self.metric);
+        assert_eq!(
+            distances.len(),
+            self.divergence_times.len(),
+            "the collection has as many neighbours as the tracker"
+        );
+
+        for (divergence_time, &distance) in self.divergence_times.iter_mut().zip(&distances) {
+            if divergence_time.is_none() && distance >= self.threshold {
+                *divergence_time = Some(time);
+            }
+        }
+
+        self.times.push(time);
+        self.distances.push(distances);
+    }
+
+    /// Follows members inserted at `indices`, in ascending order like
+    /// [`Refinement::refine`](crate::core::refinement::Refinement::refine) returns them.
+    /// A new member splits a pair in two, both start over without a divergence time
+    /// and have NaN distances in the recordings from before.
+    pub fn insert_members(&mut self, indices: &[usize]) {
+        for &index in indices {
+            let pair = index - 1;
+
+            self.divergence_times[pair] = None;
+            self.divergence_times.insert(pair, None);
+            for distances in &mut self.distances {
+                distances[pair] = f64::NAN;
+                distances.insert(pair, f64::NAN);
+            }
+        }
+    }
+
+    /// One `time,pair,distance` line per pair and recording
+    pub fn write_profile_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(writer, "time,pair,distance")?;
+
+        for (time, distances) in self.times.iter().zip(&self.distances) {
+            for (pair, distance) in distances.iter().enumerate() {
+                writeln!(writer, "{},{},{}", time, pair, distance)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// One `pair,divergence_time` line per pair, with an empty time if it didn't diverge
+    pub fn write_divergence_times_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(writer, "pair,divergence_time")?;
+
+        for (pair, divergence_time) in self.divergence_times.iter().enumerate() {
+            match divergence_time {
+                Some(time) => writeln!(writer, "{},{}", pair, time)?,
+                None => writeln!(writer, "{},", pair)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_neighbours_diverge() {
+    use crate::core::distance::TorusEuclidean;
+    use crate::core::environment::Environment;
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+    use std::time::Duration;
+
+    // Neighbours 2.5e-7 apart in the first angle, released from (angle_a, angle_b)
+    let neighbours = |angle_a: f64, angle_b: f64| {
+        let configurations = (0..5)
+            .map(|i| {
+                DoublePendulumConfiguration::new(
+                    PendulumConfiguration::new(angle_a + i as f64 * 2.5e-7, 0.0),
+                    PendulumConfiguration::new(angle_b, 0.0),
+                )
+            })
+            .collect();
+
+        DoublePendulumCollection::new(
+            Pendulum::new(1.0, 1.0),
+            Pendulum::new(1.0, 1.0),
+            configurations,
+        )
+        .with_environment(Environment::new(9.81, 0.0, 1.0))
+    };
+    // High up the motion is chaotic, close to hanging down it is regular
+    let mut chaotic = neighbours(2.0, 2.8);
+    let mut regular = neighbours(0.1, 0.1);
+
+    let mut chaotic_tracker = DivergenceTracker::new(TorusEuclidean, 0.1, &chaotic);
+    let mut regular_tracker = DivergenceTracker::new(TorusEuclidean, 0.1, &regular);
+    chaotic_tracker.record(&chaotic);
+    regular_tracker.record(&regular);
+    assert_eq!(chaotic_tracker.diverged_count(), 0);
+
+    for _ in 0..100 {
+        for collection in [&mut chaotic, &mut regular] {
+            collection.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.01), 20);
+        }
+        chaotic_tracker.record(&chaotic);
+        regular_tracker.record(&regular);
+    }
+
+    assert_eq!(chaotic_tracker.times().len(), 101);
+    assert!(chaotic_tracker.distances().iter().all(|row| row.len() == 4));
+    // Chaos takes a while to blow up 2.5e-7 to macroscopic distances
+    for divergence_time in chaotic_tracker.divergence_times() {
+        let divergence_time = divergence_time.expect("diverged");
+        assert!(divergence_time > 1.0, "{}", divergence_time);
+    }
+    assert_eq!(regular_tracker.diverged_count(), 0);
+
+    let mut csv = Vec::new();
+    chaotic_tracker.write_profile_csv(&mut csv).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 1 + 101 * 4);
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+/// A scalar function of simulated time
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Signal {
+    #[default]
+    Zero,
+    Constant(f64),
+    /// `amplitude * sin(angular_frequency * time + phase)`
+    Sinusoidal {
+        amplitude: f64,
+        /// Radians per \[time\]
+        angular_frequency: f64,
+        /// Radians
+        phase: f64,
+    },
+}
+
+impl Signal {
+    pub fn sinusoidal(amplitude: f64, angular_frequency: f64, phase: f64) -> Self {
+        Signal::Sinusoidal {
+            amplitude,
+            angular_frequency,
+            phase,
+        }
+    }
+
+    /// The acceleration of a pivot oscillating with `amplitude * sin(angular_frequency * time + phase)`
+    pub fn sinusoidal_displacement(amplitude: f64, angular_frequency: f64, phase: f64) -> Self {
+        Signal::sinusoidal(
+            -amplitude * angular_frequency * angular_frequency,
+            angular_frequency,
+            phase,
+        )
+    }
+
+    pub fn value(&self, time: f64) -> f64 {
+        match *self {
+            Signal::Zero => 0.0,
+            Signal::Constant(value) => value,
+            Signal::Sinusoidal {
+                amplitude,
+                angular_frequency,
+                phase,
+            } => amplitude * f64::sin(angular_frequency * time + phase),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Signal::Zero)
+    }
+}
+
+/// External forcing of the double pendulum. Torques act at the same joints as [`Damping`](crate::core::damping::Damping).
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Driving {
+    /// Length per \[time\]^2
+    pivot_acceleration_x: Signal,
+    /// Length per \[time\]^2, positive is up
+    pivot_acceleration_y: Signal,
+    /// Torque the pivot applies to the first arm
+    torque_a: Signal,
+    /// Torque the first arm applies to the second arm at the joint between them
+    torque_b: Signal,
+}
+
+impl Driving {
+    pub fn new(
+        pivot_acceleration_x: Signal,
+        pivot_acceleration_y: Signal,
+        torque_a: Signal,
+        torque_b: Signal,
+    ) -> Self {
+        Driving {
+            pivot_acceleration_x,
+            pivot_acceleration_y,
+            torque_a,
+            torque_b,
+        }
+    }
+
+    pub fn undriven() -> Self {
+        Driving::default()
+    }
+
+    pub fn pivot_acceleration_x(&self) -> &Signal {
+        &self.pivot_acceleration_x
+    }
+
+    pub fn pivot_acceleration_y(&self) -> &Signal {
+        &self.pivot_acceleration_y
+    }
+
+    pub fn torque_a(&self) -> &Signal {
+        &self.torque_a
+    }
+
+    pub fn torque_b(&self) -> &Signal {
+        &self.torque_b
+    }
+
+    pub fn is_undriven(&self) -> bool {
+        self.pivot_acceleration_x.is_zero()
+            && self.pivot_acceleration_y.is_zero()
+            && self.torque_a.is_zero()
+            && self.torque_b.is_zero()
+    }
+}
+
+#[test]
+fn test_constant_pivot_acceleration_tilts_gravity() {
+    use crate::core::environment::Environment;
+    use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+
+    let pendulum_a = Pendulum::new(1.0, 2.0);
+    let pendulum_b = Pendulum::new(1.5, 1.0);
+    let configuration = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.0, -0.5),
+        PendulumConfiguration::new(-1.0, 3.0),
+    );
+
+    // Accelerating the pivot to the right looks like gravity pulling to the left
+    let driven = Environment::new(9.0, 0.0, 1.0).with_driving(Driving::new(
+        Signal::Constant(12.0),
+        Signal::Zero,
+        Signal::Zero,
+        Signal::Zero,
+    ));
+    let tilted = Environment::new(15.0, f64::atan2(-12.0, 9.0), 1.0);
+
+    let (driven_a, driven_b) =
+        configuration.angular_accelerations(&pendulum_a, &pendulum_b, &driven, 3.0);
+    let (tilted_a, tilted_b) =
+        configuration.angular_accelerations(&pendulum_a, &pendulum_b, &tilted, 3.0);
+
+    assert!(
+        (driven_a - tilted_a).abs() < 1e-9,
+        "{} {}",
+        driven_a,
+        tilted_a
+    );
+    assert!(
+        (driven_b - tilted_b).abs() < 1e-9,
+        "{} {}",
+        driven_b,
+        tilted_b
+    );
+}
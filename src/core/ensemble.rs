@@ -0,0 +1,464 @@
+use crate::core::random::{ConfigurationDistribution, RandomSource};
+use crate::core::util::normalize_angle;
+use crate::core::{DoublePendulumCollection, DoublePendulumConfiguration, Pendulum};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+/// One of the four numbers making up a [`DoublePendulumConfiguration`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum StateVariable {
+    AngleA,
+    AngularVelocityA,
+    AngleB,
+    AngularVelocityB,
+}
+
+impl StateVariable {
+    pub const ALL: [StateVariable; 4] = [
+        StateVariable::AngleA,
+        StateVariable::AngularVelocityA,
+        StateVariable::AngleB,
+        StateVariable::AngularVelocityB,
+    ];
+
+    pub fn get(&self, configuration: &DoublePendulumConfiguration) -> f64 {
+        match self {
+            StateVariable::AngleA => configuration.a.angle,
+            StateVariable::AngularVelocityA => configuration.a.angular_velocity,
+            StateVariable::AngleB => configuration.b.angle,
+            StateVariable::AngularVelocityB => configuration.b.angular_velocity,
+        }
+    }
+
+    pub fn is_angle(&self) -> bool {
+        matches!(self, StateVariable::AngleA | StateVariable::AngleB)
+    }
+
+    pub fn set(&self, configuration: &mut DoublePendulumConfiguration, value: f64) {
+        match self {
+            StateVariable::AngleA => configuration.a.angle = normalize_angle(value),
+            StateVariable::AngularVelocityA => configuration.a.angular_velocity = value,
+            StateVariable::AngleB => configuration.b.angle = normalize_angle(value),
+            StateVariable::AngularVelocityB => configuration.b.angular_velocity = value,
+        }
+    }
+}
+
+/// The values a [`StateVariable`] takes in an ensemble, from `start` to `end` inclusive
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ParameterRange {
+    variable: StateVariable,
+    start: f64,
+    end: f64,
+}
+
+impl ParameterRange {
+    pub fn new(variable: StateVariable, start: f64, end: f64) -> Self {
+        ParameterRange {
+            variable,
+            start,
+            end,
+        }
+    }
+
+    pub fn variable(&self) -> StateVariable {
+        self.variable
+    }
+
+    pub fn start(&self) -> f64 {
+        self.start
+    }
+
+    pub fn end(&self) -> f64 {
+        self.end
+    }
+
+    /// The value at `fraction` of the way from `start` to `end`
+    pub fn interpolate(&self, fraction: f64) -> f64 {
+        self.start + (self.end - self.start) * fraction
+    }
+
+    /// `count` evenly spaced values, both ends included
+    fn evenly_spaced(&self, count: usize) -> impl Iterator<Item = f64> + '_ {
+        let denominator = usize::max(count, 2) - 1;
+        (0..count).map(move |i| self.interpolate(i as f64 / denominator as f64))
+    }
+}
+
+/// Which coordinates every member of a collection was generated from
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EnsembleOrigins {
+    /// What the coordinates of each member mean
+    variables: Vec<StateVariable>,
+    /// Number of members along each variable, for sweeps and grids.
+    /// The first variable changes fastest.
+    shape: Option<Vec<usize>>,
+    /// One entry per member, one coordinate per variable
+    coordinates: Vec<Vec<f64>>,
+}
+
+impl EnsembleOrigins {
+    pub fn variables(&self) -> &[StateVariable] {
+        &self.variables
+    }
+
+    pub fn shape(&self) -> Option<&[usize]> {
+        self.shape.as_deref()
+    }
+
+    pub fn coordinates(&self) -> &[Vec<f64>] {
+        &self.coordinates
+    }
+
+    pub fn coordinates_of(&self, index: usize) -> &[f64] {
+        &self.coordinates[index]
+    }
+
+    /// Inserts a member halfway between the members at `index` and `index + 1`.
+    /// The members don't form a sweep or grid anymore after that.
+    pub(crate) fn insert_midpoint(&mut self, index: usize) {
+        let midpoint = self.coordinates[index]
+            .iter()
+            .zip(&self.coordinates[index + 1])
+            .map(|(first, second)| (first + second) / 2.0)
+            .collect();
+
+        self.coordinates.insert(index + 1, midpoint);
+        self.shape = None;
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Sampling {
+    Sweep(ParameterRange, usize),
+    Grid([ParameterRange; 2], [usize; 2]),
+    LatinHypercube(Vec<ParameterRange>, usize, u64),
+    Sobol(Vec<ParameterRange>, usize),
+    Cloud(RandomSource, usize),
+}
+
+/// Builds [`DoublePendulumCollection`]s from structured families of initial conditions.
+/// Everything that isn't varied is taken from the base configuration.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EnsembleBuilder {
+    pendulum_a: Pendulum,
+    pendulum_b: Pendulum,
+    base: DoublePendulumConfiguration,
+    sampling: Option<Sampling>,
+}
+
+impl EnsembleBuilder {
+    pub fn new(
+        pendulum_a: Pendulum,
+        pendulum_b: Pendulum,
+        base: DoublePendulumConfiguration,
+    ) -> Self {
+        EnsembleBuilder {
+            pendulum_a,
+            pendulum_b,
+            base,
+            sampling: None,
+        }
+    }
+
+    /// `count` members evenly spaced along `range`
+    pub fn sweep(mut self, range: ParameterRange, count: usize) -> Self {
+        self.sampling = Some(Sampling::Sweep(range, count));
+        self
+    }
+
+    /// `x_count * y_count` members evenly spaced on a grid
+    ///
+    /// # Panics
+    /// If both ranges vary the same variable
+    pub fn grid(
+        mut self,
+        x_range: ParameterRange,
+        x_count: usize,
+        y_range: ParameterRange,
+        y_count: usize,
+    ) -> Self {
+        assert_distinct(&[x_range, y_range]);
+
+        self.sampling = Some(Sampling::Grid([x_range, y_range], [x_count, y_count]));
+        self
+    }
+
+    /// `count` members, every range split into `count` strata with exactly one member in each
+    ///
+    /// # Panics
+    /// If two ranges vary the same variable
+    pub fn latin_hypercube(mut self, ranges: Vec<ParameterRange>, count: usize, seed: u64) -> Self {
+        assert_distinct(&ranges);
+
+        self.sampling = Some(Sampling::LatinHypercube(ranges, count, seed));
+        self
+    }
+
+    /// `count` members from the Sobol low-discrepancy sequence
+    ///
+    /// # Panics
+    /// If two ranges vary the same variable
+    pub fn sobol(mut self, ranges: Vec<ParameterRange>, count: usize) -> Self {
+        assert_distinct(&ranges);
+
+        self.sampling = Some(Sampling::Sobol(ranges, count));
+        self
+    }
+
+    /// `count` members drawn from `distribution`, ignoring the base configuration.
+    /// See [`ConfigurationDistribution::around`] for a cloud around it.
+    pub fn cloud(
+        mut self,
+        distribution: ConfigurationDistribution,
+        count: usize,
+        seed: u64,
+    ) -> Self {
+        self.sampling = Some(Sampling::Cloud(
+            RandomSource::new(seed, distribution),
+            count,
+        ));
+        self
+    }
+
+    /// # Panics
+    /// If no sampling was chosen
+    pub fn build(self) -> DoublePendulumCollection {
+        let sampling = self.sampling.expect("a sampling was chosen");
+
+        let (variables, shape, coordinates, random_source) = match sampling {
+            Sampling::Sweep(range, count) => (
+                vec![range.variable],
+                Some(vec![count]),
+                range.evenly_spaced(count).map(|x| vec![x]).collect(),
+                None,
+            ),
+            Sampling::Grid([x_range, y_range], [x_count, y_count]) => (
+                vec![x_range.variable, y_range.variable],
+                Some(vec![x_count, y_count]),
+                y_range
+                    .evenly_spaced(y_count)
+                    .flat_map(|y| x_range.evenly_spaced(x_count).map(move |x| vec![x, y]))
+                    .collect(),
+                None,
+            ),
+            Sampling::LatinHypercube(ranges, count, seed) => (
+                variables_of(&ranges),
+                None,
+                scale_unit_samples(&ranges, latin_hypercube(ranges.len(), count, seed)),
+                None,
+            ),
+            Sampling::Sobol(ranges, count) => (
+                variables_of(&ranges),
+                None,
+                scale_unit_samples(&ranges, sobol(ranges.len(), count)),
+                None,
+            ),
+            Sampling::Cloud(random_source, count) => (
+                StateVariable::ALL.to_vec(),
+                None,
+                random_source
+                    .sample(count)
+                    .iter()
+                    .map(|configuration| {
+                        StateVariable::ALL
+                            .iter()
+                            .map(|variable| variable.get(configuration))
+                            .collect()
+                    })
+                    .collect(),
+                Some(random_source),
+            ),
+        };
+
+        let pendulum_configurations = coordinates
+            .iter()
+            .map(|member_coordinates: &Vec<f64>| {
+                let mut configuration = self.base;
+                for (variable, &value) in variables.iter().zip(member_coordinates) {
+                    variable.set(&mut configuration, value);
+                }
+                configuration
+            })
+            .collect();
+
+        let mut collection = DoublePendulumCollection::new(
+            self.pendulum_a,
+            self.pendulum_b,
+            pendulum_configurations,
+        );
+        collection.random_source = random_source;
+        collection.origins = Some(EnsembleOrigins {
+            variables,
+            shape,
+            coordinates,
+        });
+        collection
+    }
+}
+
+fn assert_distinct(ranges: &[ParameterRange]) {
+    for (i, range) in ranges.iter().enumerate() {
+        assert!(
+            ranges[..i]
+                .iter()
+                .all(|other| other.variable != range.variable),
+            "every variable can only be varied once"
+        );
+    }
+}
+
+fn variables_of(ranges: &[ParameterRange]) -> Vec<StateVariable> {
+    ranges.iter().map(|range| range.variable).collect()
+}
+
+fn scale_unit_samples(ranges: &[ParameterRange], samples: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    samples
+        .into_iter()
+        .map(|sample| {
+            ranges
+                .iter()
+                .zip(sample)
+                .map(|(range, fraction)| range.interpolate(fraction))
+                .collect()
+        })
+        .collect()
+}
+
+/// `count` points in the unit hypercube of `dimensions` dimensions
+fn latin_hypercube(dimensions: usize, count: usize, seed: u64) -> Vec<Vec<f64>> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut samples = vec![Vec::with_capacity(dimensions); count];
+
+    for _ in 0..dimensions {
+        let mut strata: Vec<_> = (0..count).collect();
+        strata.shuffle(&mut rng);
+
+        for (sample, stratum) in samples.iter_mut().zip(strata) {
+            sample.push((stratum as f64 + rng.gen::<f64>()) / count as f64);
+        }
+    }
+
+    samples
+}
+
+/// Primitive polynomial degree, coefficients and initial direction numbers
+/// for the first four Sobol dimensions (after Joe and Kuo)
+const SOBOL_PARAMETERS: [(usize, u32, [u32; 3]); 4] = [
+    (0, 0, [0, 0, 0]),
+    (1, 0, [1, 0, 0]),
+    (2, 1, [1, 3, 0]),
+    (3, 1, [1, 3, 1]),
+];
+
+const SOBOL_BITS: usize = 32;
+
+/// The first `count` points of the Sobol sequence, starting at the origin
+fn sobol(dimensions: usize, count: usize) -> Vec<Vec<f64>> {
+    assert!(
+        dimensions <= SOBOL_PARAMETERS.len(),
+        "only {} Sobol dimensions are supported",
+        SOBOL_PARAMETERS.len()
+    );
+
+    let directions: Vec<[u32; SOBOL_BITS]> = SOBOL_PARAMETERS[..dimensions]
+        .iter()
+        .map(|&(degree, coefficients, initial)| {
+            let mut direction = [0; SOBOL_BITS];
+            for k in 0..SOBOL_BITS {
+                direction[k] = if degree == 0 {
+                    1 << (SOBOL_BITS - 1 - k)
+                } else if k < degree {
+                    initial[k] << (SOBOL_BITS - 1 - k)
+                } else {
+                    let mut value = direction[k - degree] ^ (direction[k - degree] >> degree);
+                    for i in 1..degree {
+                        value ^= ((coefficients >> (degree - 1 - i)) & 1) * direction[k - i];
+                    }
+                    value
+                };
+            }
+            direction
+        })
+        .collect();
+
+    let mut point = vec![0u32; dimensions];
+    (0..count)
+        .map(|i| {
+            let sample = point
+                .iter()
+                .map(|&value| value as f64 / (1u64 << SOBOL_BITS) as f64)
+                .collect();
+
+            // Gray code order, flipping the direction number of the lowest zero bit of i
+            let bit = (!i).trailing_zeros() as usize;
+            for (value, direction) in point.iter_mut().zip(&directions) {
+                *value ^= direction[bit];
+            }
+
+            sample
+        })
+        .collect()
+}
+
+#[test]
+fn test_ensemble_samplings() {
+    use crate::core::PendulumConfiguration;
+
+    let pendulum_a = Pendulum::new(1.0, 2.0);
+    let pendulum_b = Pendulum::new(1.5, 1.0);
+    let base = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.0, 0.0),
+        PendulumConfiguration::new(-1.0, 3.0),
+    );
+    let builder = EnsembleBuilder::new(pendulum_a, pendulum_b, base);
+    let angle_a = ParameterRange::new(StateVariable::AngleA, -1.0, 1.0);
+    let velocity_b = ParameterRange::new(StateVariable::AngularVelocityB, 0.0, 4.0);
+
+    let grid = builder.clone().grid(angle_a, 5, velocity_b, 3).build();
+    let origins = grid.origins().unwrap();
+    assert_eq!(grid.pendulum_configurations().len(), 15);
+    assert_eq!(origins.shape(), Some([5, 3].as_ref()));
+    assert_eq!(origins.coordinates_of(0), &[-1.0, 0.0]);
+    assert_eq!(origins.coordinates_of(6), &[-0.5, 2.0]);
+    assert_eq!(origins.coordinates_of(14), &[1.0, 4.0]);
+    for (configuration, coordinates) in grid
+        .pendulum_configurations()
+        .iter()
+        .zip(origins.coordinates())
+    {
+        assert_eq!(configuration.a_configuration().angle(), coordinates[0]);
+        assert_eq!(
+            configuration.b_configuration().angular_velocity(),
+            coordinates[1]
+        );
+        assert_eq!(configuration.a_configuration().angular_velocity(), 0.0);
+        assert_eq!(configuration.b_configuration().angle(), -1.0);
+    }
+
+    // One member in each stratum of each range
+    for collection in [
+        builder
+            .clone()
+            .latin_hypercube(vec![angle_a, velocity_b], 8, 3)
+            .build(),
+        builder.clone().sobol(vec![angle_a, velocity_b], 8).build(),
+    ] {
+        for (dimension, range) in [angle_a, velocity_b].iter().enumerate() {
+            let mut strata: Vec<_> = collection
+                .origins()
+                .unwrap()
+                .coordinates()
+                .iter()
+                .map(|coordinates| {
+                    ((coordinates[dimension] - range.start()) / (range.end() - range.start()) * 8.0)
+                        as usize
+                })
+                .collect();
+            strata.sort_unstable();
+            assert_eq!(strata, (0..8).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::core::driving::Driving;
+use crate::core::util::{Point, GRAVITY};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The physics the pendulums live in
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Environment {
+    /// Length per \[time\]^2
+    gravity: f64,
+    /// Radians, counterclockwise from straight down
+    gravity_direction: f64,
+    /// \[time\] simulated per second of step duration
+    time_scale: f64,
+    #[serde(default)]
+    driving: Driving,
+}
+
+impl Environment {
+    /// # Panics
+    /// If `time_scale` is negative
+    pub fn new(gravity: f64, gravity_direction: f64, time_scale: f64) -> Self {
+        assert!(time_scale >= 0.0, "time_scale must not be negative");
+
+        Environment {
+            gravity,
+            gravity_direction,
+            time_scale,
+            driving: Driving::undriven(),
+        }
+    }
+
+    pub fn with_driving(mut self, driving: Driving) -> Self {
+        self.driving = driving;
+        self
+    }
+
+    pub fn gravity(&self) -> f64 {
+        self.gravity
+    }
+
+    pub fn gravity_direction(&self) -> f64 {
+        self.gravity_direction
+    }
+
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    pub fn driving(&self) -> &Driving {
+        &self.driving
+    }
+
+    /// Gravitational acceleration as a vector
+    pub fn gravity_vector(&self) -> Point {
+        Point {
+            x: self.gravity * self.gravity_direction.sin(),
+            y: -self.gravity * self.gravity_direction.cos(),
+        }
+    }
+
+    /// How much simulated time passes in a step of `duration`
+    pub fn scale(&self, duration: Duration) -> Duration {
+        duration.mul_f64(self.time_scale)
+    }
+}
+
+/// The environment the project always used: [`GRAVITY`] pointing straight down, no time scaling
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new(GRAVITY, 0.0, 1.0)
+    }
+}
@@ -0,0 +1,528 @@
+use crate::core::ensemble::StateVariable;
+use crate::core::environment::Environment;
+use crate::core::integrator::Integrator;
+use crate::core::lyapunov::state_difference;
+use crate::core::poincare::CrossingDirection;
+use crate::core::util::normalize_angle;
+use crate::core::{pendulum_pair, DoublePendulumCollection, DoublePendulumConfiguration, Pendulum};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A scalar function of the state. Its zero crossings are events.
+pub trait EventFunction: Sync {
+    fn value(
+        &self,
+        configuration: &DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> f64;
+}
+
+impl<F> EventFunction for F
+where
+    F: Fn(&DoublePendulumConfiguration, &Pendulum, &Pendulum, &Environment, f64) -> f64 + Sync,
+{
+    fn value(
+        &self,
+        configuration: &DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> f64 {
+        self(configuration, pendulum_a, pendulum_b, environment, time)
+    }
+}
+
+/// A state variable passing `value`, the short way around for angles
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StateCrossing {
+    variable: StateVariable,
+    value: f64,
+}
+
+impl StateCrossing {
+    pub fn new(variable: StateVariable, value: f64) -> Self {
+        StateCrossing { variable, value }
+    }
+}
+
+impl EventFunction for StateCrossing {
+    fn value(
+        &self,
+        configuration: &DoublePendulumConfiguration,
+        _pendulum_a: &Pendulum,
+        _pendulum_b: &Pendulum,
+        _environment: &Environment,
+        _time: f64,
+    ) -> f64 {
+        let difference = self.variable.get(configuration) - self.value;
+
+        if self.variable.is_angle() {
+            normalize_angle(difference)
+        } else {
+            difference
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Bob {
+    A,
+    B,
+}
+
+/// A bob's height above the pivot passing `height`
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BobHeight {
+    bob: Bob,
+    height: f64,
+}
+
+impl BobHeight {
+    pub fn new(bob: Bob, height: f64) -> Self {
+        BobHeight { bob, height }
+    }
+}
+
+impl EventFunction for BobHeight {
+    fn value(
+        &self,
+        configuration: &DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        _environment: &Environment,
+        _time: f64,
+    ) -> f64 {
+        let (a_position, b_position) = configuration.positions(pendulum_a, pendulum_b);
+        let position = match self.bob {
+            Bob::A => a_position,
+            Bob::B => b_position,
+        };
+
+        position.y - self.height
+    }
+}
+
+/// The total energy passing `energy`, for example while damping drains it
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EnergyLevel {
+    energy: f64,
+}
+
+impl EnergyLevel {
+    pub fn new(energy: f64) -> Self {
+        EnergyLevel { energy }
+    }
+}
+
+impl EventFunction for EnergyLevel {
+    fn value(
+        &self,
+        configuration: &DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        _time: f64,
+    ) -> f64 {
+        configuration.total_energy(pendulum_a, pendulum_b, environment) - self.energy
+    }
+}
+
+pub struct Event {
+    function: Box<dyn EventFunction>,
+    direction: CrossingDirection,
+    /// Whether a member stops being stepped once this happens
+    terminal: bool,
+}
+
+impl Event {
+    pub fn new(function: impl EventFunction + 'static, direction: CrossingDirection) -> Self {
+        Event {
+            function: Box::new(function),
+            direction,
+            terminal: false,
+        }
+    }
+
+    /// Stops a member at the state where this event happens
+    pub fn terminal(mut self) -> Self {
+        self.terminal = true;
+        self
+    }
+
+    pub fn function(&self) -> &dyn EventFunction {
+        self.function.as_ref()
+    }
+
+    pub fn direction(&self) -> CrossingDirection {
+        self.direction
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EventOccurrence {
+    /// Index into the tracker's events
+    event: usize,
+    time: f64,
+    /// The state when it happened
+    configuration: DoublePendulumConfiguration,
+}
+
+impl EventOccurrence {
+    pub fn event(&self) -> usize {
+        self.event
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn configuration(&self) -> &DoublePendulumConfiguration {
+        &self.configuration
+    }
+}
+
+/// Steps a [`DoublePendulumCollection`] while watching every member for events.
+///
+/// Within a step, events are located by bisection on the cubic Hermite interpolant through
+/// both ends of the step. Sign changes where the function jumps instead of passing zero,
+/// like an angle wrapping around, are ignored.
+pub struct EventTracker {
+    events: Vec<Event>,
+    /// One entry per member, in the order they happened
+    occurrences: Vec<Vec<EventOccurrence>>,
+    /// Members that hit a terminal event
+    stopped: Vec<bool>,
+}
+
+impl EventTracker {
+    pub fn new(events: Vec<Event>, collection: &DoublePendulumCollection) -> Self {
+        let members = collection.pendulum_configurations().len();
+
+        EventTracker {
+            events,
+            occurrences: vec![Vec::new(); members],
+            stopped: vec![false; members],
+        }
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn occurrences(&self) -> &[Vec<EventOccurrence>] {
+        &self.occurrences
+    }
+
+    pub fn is_stopped(&self, index: usize) -> bool {
+        self.stopped[index]
+    }
+
+    /// Like [`DoublePendulumCollection::step_all_n_times`], but members that hit a terminal event stay where it happened
+    ///
+    /// # Panics
+    /// If the collection doesn't have as many members as the tracker,
+    /// use [`Refinement::refine_tracked`](crate::core::refinement::Refinement::refine_tracked) to refine tracked collections
+    pub fn step_all_n_times(
+        &mut self,
+        integrator: &(impl Integrator + Sync),
+        collection: &mut DoublePendulumCollection,
+        step_time: Duration,
+        n: u32,
+    ) {
+        assert_eq!(
+            collection.pendulum_configurations.len(),
+            self.stopped.len(),
+            "the collection has as many members as the tracker"
+        );
+
+        let shared_pendulums = (&collection.pendulum_a, &collection.pendulum_b);
+        let member_pendulums = &collection.member_pendulums;
+        let environment = &collection.environment;
+        let time = collection.clock.time();
+        let events = &self.events;
+
+        collection
+            .pendulum_configurations
+            .par_iter_mut()
+            .zip(self.occurrences.par_iter_mut())
+            .zip(self.stopped.par_iter_mut())
+            .enumerate()
+            .filter(|(_, (_, stopped))| !**stopped)
+            .for_each(|(index, ((configuration, occurrences), stopped))| {
+                let (pendulum_a, pendulum_b) =
+                    pendulum_pair(shared_pendulums, member_pendulums, index);
+
+                *stopped = step_member(
+                    events,
+                    integrator,
+                    configuration,
+                    occurrences,
+                    pendulum_a,
+                    pendulum_b,
+                    environment,
+                    time,
+                    step_time,
+                    n,
+                );
+            });
+
+        collection
+            .clock
+            .advance(environment.scale(step_time).as_secs_f64(), n);
+        collection.record_steps(step_time, n);
+    }
+
+    /// Makes room for a member inserted at `index` that went through `occurrences`
+    /// and was `stopped` by a terminal event or not
+    pub(crate) fn insert_member(
+        &mut self,
+        index: usize,
+        occurrences: Vec<EventOccurrence>,
+        stopped: bool,
+    ) {
+        self.occurrences.insert(index, occurrences);
+        self.stopped.insert(index, stopped);
+    }
+}
+
+/// Steps a single member `n` times from `time` like [`EventTracker::step_all_n_times`] does,
+/// adding what happened to `occurrences`. Returns whether it hit a terminal event, it stays there if so.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn step_member(
+    events: &[Event],
+    integrator: &impl Integrator,
+    configuration: &mut DoublePendulumConfiguration,
+    occurrences: &mut Vec<EventOccurrence>,
+    pendulum_a: &Pendulum,
+    pendulum_b: &Pendulum,
+    environment: &Environment,
+    time: f64,
+    step_time: Duration,
+    n: u32,
+) -> bool {
+    let h = environment.scale(step_time).as_secs_f64();
+    let values_at = |configuration: &DoublePendulumConfiguration, time: f64| {
+        events
+            .iter()
+            .map(|event| {
+                event
+                    .function
+                    .value(configuration, pendulum_a, pendulum_b, environment, time)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut values = values_at(configuration, time);
+    for i in 0..n {
+        let step_start = time + i as f64 * h;
+        let previous = *configuration;
+        configuration.step(
+            integrator,
+            pendulum_a,
+            pendulum_b,
+            environment,
+            step_start,
+            step_time,
+        );
+        let next_values = values_at(configuration, step_start + h);
+
+        let mut found: Vec<_> = events
+            .iter()
+            .enumerate()
+            .filter(|&(j, event)| event.direction.crosses(values[j], next_values[j]))
+            .filter_map(|(j, event)| {
+                locate_event(
+                    event,
+                    &previous,
+                    configuration,
+                    pendulum_a,
+                    pendulum_b,
+                    environment,
+                    step_start,
+                    h,
+                )
+                .map(|(time, configuration)| EventOccurrence {
+                    event: j,
+                    time,
+                    configuration,
+                })
+            })
+            .collect();
+        found.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        for occurrence in found {
+            occurrences.push(occurrence);
+
+            if events[occurrence.event].terminal {
+                *configuration = occurrence.configuration;
+                return true;
+            }
+        }
+
+        values = next_values;
+    }
+
+    false
+}
+
+/// Time and state of `event` within the step from `previous` to `next`, which starts at `time` and takes `h` \[time\]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn locate_event(
+    event: &Event,
+    previous: &DoublePendulumConfiguration,
+    next: &DoublePendulumConfiguration,
+    pendulum_a: &Pendulum,
+    pendulum_b: &Pendulum,
+    environment: &Environment,
+    time: f64,
+    h: f64,
+) -> Option<(f64, DoublePendulumConfiguration)> {
+    const BISECTIONS: usize = 60;
+
+    let previous_derivative = previous.derivative(pendulum_a, pendulum_b, environment, time);
+    let next_derivative = next.derivative(pendulum_a, pendulum_b, environment, time + h);
+    let difference = state_difference(next, previous);
+
+    let state_at = |s: f64| {
+        let mut state = previous.offset(
+            &hermite_offset(&previous_derivative, &difference, &next_derivative, h, s),
+            1.0,
+        );
+        state.wrap_angles();
+        state
+    };
+    let value_at = |s: f64| {
+        event.function.value(
+            &state_at(s),
+            pendulum_a,
+            pendulum_b,
+            environment,
+            time + s * h,
+        )
+    };
+
+    let (mut low, mut high) = (0.0, 1.0);
+    let (mut low_value, mut high_value) = (value_at(low), value_at(high));
+    let jump = (high_value - low_value).abs();
+    for _ in 0..BISECTIONS {
+        let middle = (low + high) / 2.0;
+        let middle_value = value_at(middle);
+        if (middle_value < 0.0) == (low_value < 0.0) {
+            low = middle;
+            low_value = middle_value;
+        } else {
+            high = middle;
+            high_value = middle_value;
+        }
+    }
+
+    // A continuous function is almost flat over the last bisection interval, a jump is not
+    if (high_value - low_value).abs() > 0.5 * jump {
+        return None;
+    }
+
+    Some((time + high * h, state_at(high)))
+}
+
+/// Offset from the start of a step after fraction `s` of it, on the cubic Hermite interpolant
+/// through both ends of the step with their derivatives. `difference` is end minus start.
+fn hermite_offset(
+    start_derivative: &[f64; 4],
+    difference: &[f64; 4],
+    end_derivative: &[f64; 4],
+    h: f64,
+    s: f64,
+) -> [f64; 4] {
+    let (s2, s3) = (s * s, s * s * s);
+    let h10 = s3 - 2.0 * s2 + s;
+    let h01 = -2.0 * s3 + 3.0 * s2;
+    let h11 = s3 - s2;
+
+    let mut offset = [0.0; 4];
+    for (i, offset) in offset.iter_mut().enumerate() {
+        *offset = h10 * h * start_derivative[i] + h01 * difference[i] + h11 * h * end_derivative[i];
+    }
+    offset
+}
+
+#[test]
+fn test_events_are_located_and_stop_members() {
+    use crate::core::damping::Damping;
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::PendulumConfiguration;
+    use std::f64::consts::PI;
+
+    let pendulum = Pendulum::new(1.0, 1.0).with_damping(Damping::new(0.01, 0.0, 0.0));
+    let environment = Environment::new(9.81, 0.0, 1.0);
+    let initial = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(1.5, 0.0),
+        PendulumConfiguration::new(-1.0, 0.0),
+    );
+    let mut collection = DoublePendulumCollection::new(pendulum, pendulum, vec![initial; 2])
+        .with_environment(environment);
+
+    let target_energy = initial.total_energy(&pendulum, &pendulum, &environment) - 2.0;
+    let mut tracker = EventTracker::new(
+        vec![
+            Event::new(
+                StateCrossing::new(StateVariable::AngleA, 0.0),
+                CrossingDirection::Both,
+            ),
+            // The second arm swings through 0, where its distance from PI jumps from -PI to PI without crossing 0
+            Event::new(
+                StateCrossing::new(StateVariable::AngleB, PI),
+                CrossingDirection::Both,
+            ),
+            Event::new(
+                EnergyLevel::new(target_energy),
+                CrossingDirection::Descending,
+            )
+            .terminal(),
+        ],
+        &collection,
+    );
+    tracker.step_all_n_times(
+        &RungeKutta4,
+        &mut collection,
+        Duration::from_secs_f64(0.01),
+        1_000,
+    );
+
+    for (i, occurrences) in tracker.occurrences().iter().enumerate() {
+        assert!(tracker.is_stopped(i));
+
+        let (last, before) = occurrences.split_last().unwrap();
+        assert_eq!(last.event(), 2);
+        let energy = last
+            .configuration()
+            .total_energy(&pendulum, &pendulum, &environment);
+        assert!(
+            (energy - target_energy).abs() < 1e-6,
+            "{} {}",
+            energy,
+            target_energy
+        );
+        assert_eq!(
+            collection.pendulum_configurations()[i],
+            *last.configuration()
+        );
+
+        assert!(before.iter().any(|occurrence| occurrence.event() == 0));
+        assert!(before.iter().all(|occurrence| occurrence.event() != 1));
+        for occurrence in before {
+            assert!(occurrence.time() <= last.time());
+            if occurrence.event() == 0 {
+                assert!(occurrence.configuration().a_configuration().angle().abs() < 1e-6);
+            }
+        }
+    }
+}
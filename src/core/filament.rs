@@ -0,0 +1,256 @@
+use crate::core::util::{normalize_angle, TWO_PI};
+use crate::core::DoublePendulumCollection;
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::io::Write;
+
+/// Shape of the curve an ordered collection, like a sweep, traces through configuration space at one point in time.
+/// Members are connected in order, like [`ImageRenderer`](crate::render::image::ImageRenderer) draws them.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FilamentMetrics {
+    /// Simulated \[time\] of the collection
+    pub time: f64,
+    /// Arc length in radians on the torus of both angles, every segment taken the short way around
+    pub angle_length: f64,
+    /// Arc length \[length\] of the curve the outer bobs trace
+    pub bob_length: f64,
+    /// How often the curve turns back on itself in angle space, meaning consecutive segments point more than 90° apart
+    pub folds: usize,
+    /// Box-counting dimension in angle space, 1 for a line and approaching 2 as the curve fills the torus
+    pub fractal_dimension: f64,
+}
+
+impl FilamentMetrics {
+    /// Fitting the dimension needs at least two box sizes
+    pub const MIN_BOX_LEVELS: u32 = 2;
+    /// Finer boxes take too much memory and time to count
+    pub const MAX_BOX_LEVELS: u32 = 20;
+
+    /// `box_levels` is the number of box sizes for the fractal dimension,
+    /// halving from half the torus down to `2π / 2^box_levels`. Curves are walked in steps of half the finest box,
+    /// so the cost grows with `2^box_levels` times the angle length.
+    ///
+    /// # Errors
+    /// If `box_levels` is outside [`MIN_BOX_LEVELS`](Self::MIN_BOX_LEVELS)`..=`[`MAX_BOX_LEVELS`](Self::MAX_BOX_LEVELS)
+    pub fn measure(collection: &DoublePendulumCollection, box_levels: u32) -> Result<Self, String> {
+        check_box_levels(box_levels)?;
+
+        let configurations = collection.pendulum_configurations();
+
+        let segments: Vec<_> = configurations
+            .windows(2)
+            .map(|pair| {
+                (
+                    normalize_angle(pair[1].a.angle - pair[0].a.angle),
+                    normalize_angle(pair[1].b.angle - pair[0].b.angle),
+                )
+            })
+            .collect();
+
+        let angle_length = segments
+            .iter()
+            .map(|&(delta_a, delta_b)| f64::hypot(delta_a, delta_b))
+            .sum();
+
+        let bob_positions: Vec<_> = configurations
+            .iter()
+            .enumerate()
+            .map(|(i, configuration)| {
+                let (pendulum_a, pendulum_b) = collection.member_pendulums(i);
+                configuration.positions(pendulum_a, pendulum_b).1
+            })
+            .collect();
+        let bob_length = bob_positions
+            .windows(2)
+            .map(|pair| f64::hypot(pair[1].x - pair[0].x, pair[1].y - pair[0].y))
+            .sum();
+
+        let folds = segments
+            .windows(2)
+            .filter(|pair| pair[0].0 * pair[1].0 + pair[0].1 * pair[1].1 < 0.0)
+            .count();
+
+        let start = configurations.first().map_or((0.0, 0.0), |configuration| {
+            (configuration.a.angle, configuration.b.angle)
+        });
+
+        Ok(FilamentMetrics {
+            time: collection.time(),
+            angle_length,
+            bob_length,
+            folds,
+            fractal_dimension: box_counting_dimension(start, &segments, box_levels),
+        })
+    }
+}
+
+fn check_box_levels(box_levels: u32) -> Result<(), String> {
+    if !(FilamentMetrics::MIN_BOX_LEVELS..=FilamentMetrics::MAX_BOX_LEVELS).contains(&box_levels) {
+        return Err(format!(
+            "{} box levels, between {} and {} are supported",
+            box_levels,
+            FilamentMetrics::MIN_BOX_LEVELS,
+            FilamentMetrics::MAX_BOX_LEVELS
+        ));
+    }
+
+    Ok(())
+}
+
+/// Slope of the occupied box count over the box size, both logarithmic, fitted by least squares.
+/// Segments are walked in steps of half the smallest box so the curve is counted, not just its members.
+/// `box_levels` has to be checked with `check_box_levels`.
+fn box_counting_dimension(start: (f64, f64), segments: &[(f64, f64)], box_levels: u32) -> f64 {
+    let finest_count = 1_u64 << box_levels;
+    let finest_size = TWO_PI / finest_count as f64;
+    let cell =
+        |angle: f64| (((normalize_angle(angle) + PI) / finest_size) as u64).min(finest_count - 1);
+
+    let mut occupied = HashSet::new();
+    let (mut angle_a, mut angle_b) = start;
+    occupied.insert((cell(angle_a), cell(angle_b)));
+
+    for &(delta_a, delta_b) in segments {
+        let substeps = (f64::hypot(delta_a, delta_b) / (finest_size / 2.0))
+            .ceil()
+            .max(1.0) as u64;
+        for substep in 1..=substeps {
+            let fraction = substep as f64 / substeps as f64;
+            occupied.insert((
+                cell(angle_a + delta_a * fraction),
+                cell(angle_b + delta_b * fraction),
+            ));
+        }
+        angle_a += delta_a;
+        angle_b += delta_b;
+    }
+
+    // x is the level, ln(1 / box size) up to a constant and factor ln 2
+    let points: Vec<_> = (1..=box_levels)
+        .map(|level| {
+            let shift = box_levels - level;
+            let count = occupied
+                .iter()
+                .map(|&(a, b)| (a >> shift, b >> shift))
+                .collect::<HashSet<_>>()
+                .len();
+            (level as f64, (count as f64).log2())
+        })
+        .collect();
+
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+
+    covariance / variance
+}
+
+/// [`FilamentMetrics`] recorded over time
+#[derive(Clone, PartialEq, Debug)]
+pub struct FilamentSeries {
+    box_levels: u32,
+    metrics: Vec<FilamentMetrics>,
+}
+
+impl FilamentSeries {
+    /// See [`FilamentMetrics::measure`] for `box_levels`
+    pub fn new(box_levels: u32) -> Result<Self, String> {
+        check_box_levels(box_levels)?;
+
+        Ok(FilamentSeries {
+            box_levels,
+            metrics: Vec::new(),
+        })
+    }
+
+    pub fn box_levels(&self) -> u32 {
+        self.box_levels
+    }
+
+    pub fn metrics(&self) -> &[FilamentMetrics] {
+        &self.metrics
+    }
+
+    pub fn record(&mut self, collection: &DoublePendulumCollection) -> FilamentMetrics {
+        let metrics = FilamentMetrics::measure(collection, self.box_levels)
+            .expect("box levels are checked in new");
+        self.metrics.push(metrics);
+        metrics
+    }
+
+    /// One `time,angle_length,bob_length,folds,fractal_dimension` line per recording
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "time,angle_length,bob_length,folds,fractal_dimension"
+        )?;
+
+        for metrics in &self.metrics {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                metrics.time,
+                metrics.angle_length,
+                metrics.bob_length,
+                metrics.folds,
+                metrics.fractal_dimension
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_filament_stretches() {
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+    use std::time::Duration;
+
+    // A straight line through angle space, high enough up to get stretched and folded
+    let line = (0..600)
+        .map(|i| {
+            let fraction = i as f64 / 599.0;
+            DoublePendulumConfiguration::new(
+                PendulumConfiguration::new(1.5 + fraction, 0.0),
+                PendulumConfiguration::new(-2.5 + 5.0 * fraction, 0.0),
+            )
+        })
+        .collect();
+    let mut collection =
+        DoublePendulumCollection::new(Pendulum::new(1.0, 1.0), Pendulum::new(1.0, 1.0), line);
+
+    assert!(FilamentSeries::new(FilamentMetrics::MAX_BOX_LEVELS + 1).is_err());
+    assert!(FilamentSeries::new(1).is_err());
+    assert!(FilamentMetrics::measure(&collection, 0).is_err());
+    let mut series = FilamentSeries::new(7).unwrap();
+    let line = series.record(&collection);
+    let length = f64::hypot(1.0, 5.0);
+    assert!(
+        (line.angle_length - length).abs() < 1e-9,
+        "{}",
+        line.angle_length
+    );
+    assert_eq!(line.folds, 0);
+    assert!(
+        (line.fractal_dimension - 1.0).abs() < 0.05,
+        "{}",
+        line.fractal_dimension
+    );
+
+    collection.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.001), 5_000);
+    let stretched = series.record(&collection);
+    assert!(stretched.angle_length > 2.0 * line.angle_length);
+    assert!(stretched.bob_length > line.bob_length);
+    assert!(stretched.folds > 0);
+    assert!(stretched.fractal_dimension > line.fractal_dimension);
+
+    let mut csv = Vec::new();
+    series.write_csv(&mut csv).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 3);
+}
@@ -0,0 +1,313 @@
+use crate::core::ensemble::{EnsembleBuilder, ParameterRange, StateVariable};
+use crate::core::environment::Environment;
+use crate::core::integrator::Integrator;
+use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+use rayon::prelude::*;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::f64::consts::PI;
+use std::io::Write;
+use std::time::Duration;
+
+/// What a flip-time map covers and how finely.
+/// Deserializing checks the step and steps per check like the constructors do.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct FlipTimeSettings {
+    /// Members along `angle_a`
+    width: usize,
+    /// Members along `angle_b`
+    height: usize,
+    angle_a_range: (f64, f64),
+    angle_b_range: (f64, f64),
+    /// Members that haven't flipped after this much \[time\] never do
+    max_time: f64,
+    /// Step duration passed to the integrator, scaled with the environment's time scale
+    step: Duration,
+    /// Steps the whole grid takes at once. Members that flipped in between are stepped again
+    /// one step at a time, so this only changes speed, not the flip times.
+    steps_per_check: u32,
+}
+
+impl FlipTimeSettings {
+    /// Both angles over the full circle, checking for flips after every step
+    ///
+    /// # Panics
+    /// If `step` is zero
+    pub fn new(width: usize, height: usize, max_time: f64, step: Duration) -> Self {
+        assert!(!step.is_zero(), "step must not be zero");
+
+        FlipTimeSettings {
+            width,
+            height,
+            angle_a_range: (-PI, PI),
+            angle_b_range: (-PI, PI),
+            max_time,
+            step,
+            steps_per_check: 1,
+        }
+    }
+
+    pub fn with_angle_ranges(
+        mut self,
+        angle_a_range: (f64, f64),
+        angle_b_range: (f64, f64),
+    ) -> Self {
+        self.angle_a_range = angle_a_range;
+        self.angle_b_range = angle_b_range;
+        self
+    }
+
+    /// # Panics
+    /// If `steps_per_check` is zero
+    pub fn with_steps_per_check(mut self, steps_per_check: u32) -> Self {
+        assert!(steps_per_check > 0, "steps_per_check must not be zero");
+
+        self.steps_per_check = steps_per_check;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn angle_a_range(&self) -> (f64, f64) {
+        self.angle_a_range
+    }
+
+    pub fn angle_b_range(&self) -> (f64, f64) {
+        self.angle_b_range
+    }
+
+    pub fn max_time(&self) -> f64 {
+        self.max_time
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    pub fn steps_per_check(&self) -> u32 {
+        self.steps_per_check
+    }
+
+    /// Releases a grid of pendulums at rest and records when either arm first flips over the top.
+    /// Flips are timed to the step, members that haven't flipped after the last whole step within
+    /// the maximum time never do.
+    ///
+    /// # Panics
+    /// If the environment's time scale shrinks the step to nothing
+    pub fn generate(
+        &self,
+        integrator: &(impl Integrator + Sync),
+        pendulum_a: Pendulum,
+        pendulum_b: Pendulum,
+        environment: Environment,
+    ) -> FlipTimeMap {
+        let scaled_step_time = environment.scale(self.step).as_secs_f64();
+        assert!(
+            scaled_step_time > 0.0,
+            "the time scale must not shrink the step to zero"
+        );
+        let max_steps = (self.max_time / scaled_step_time).floor().max(0.0) as u64;
+
+        // The last member of each range sits one cell before its end, see `angles`
+        let (last_angle_a, last_angle_b) =
+            self.angles(self.width.saturating_sub(1), self.height.saturating_sub(1));
+        let mut collection = EnsembleBuilder::new(
+            pendulum_a,
+            pendulum_b,
+            DoublePendulumConfiguration::new(
+                PendulumConfiguration::new(0.0, 0.0),
+                PendulumConfiguration::new(0.0, 0.0),
+            ),
+        )
+        .grid(
+            ParameterRange::new(StateVariable::AngleA, self.angle_a_range.0, last_angle_a),
+            self.width,
+            ParameterRange::new(StateVariable::AngleB, self.angle_b_range.0, last_angle_b),
+            self.height,
+        )
+        .build()
+        .with_environment(environment);
+
+        let mut flip_times = vec![None; self.width * self.height];
+        let mut previous = collection.pendulum_configurations().to_vec();
+
+        while collection.steps() < max_steps && flip_times.iter().any(Option::is_none) {
+            let n = (max_steps - collection.steps()).min(self.steps_per_check as u64) as u32;
+            let clock = *collection.clock();
+            collection.step_all_n_times(integrator, self.step, n);
+
+            // Members that flipped somewhere in the batch go through it again step by step to find when
+            flip_times
+                .par_iter_mut()
+                .zip(previous.par_iter_mut())
+                .zip(collection.pendulum_configurations())
+                .filter(|((flip_time, _), next)| flip_time.is_none() && has_flipped(next))
+                .for_each(|((flip_time, configuration), _)| {
+                    // Same times as the collection used, so the member takes the same path
+                    let mut member_clock = clock;
+                    for i in 0..n {
+                        configuration.step(
+                            integrator,
+                            &pendulum_a,
+                            &pendulum_b,
+                            &environment,
+                            clock.time() + i as f64 * scaled_step_time,
+                            self.step,
+                        );
+                        member_clock.advance(scaled_step_time, 1);
+
+                        if has_flipped(configuration) {
+                            break;
+                        }
+                    }
+                    *flip_time = Some(member_clock.time());
+                });
+
+            previous.copy_from_slice(collection.pendulum_configurations());
+        }
+
+        FlipTimeMap {
+            settings: *self,
+            flip_times,
+        }
+    }
+
+    /// The initial angles of the member at `(x, y)`.
+    /// Each range is split into equal cells and members sit at the start of theirs,
+    /// so the end of a range is left out and a full circle doesn't have the same angle twice.
+    pub fn angles(&self, x: usize, y: usize) -> (f64, f64) {
+        let interpolate = |(start, end): (f64, f64), i: usize, count: usize| {
+            start + (end - start) * i as f64 / count as f64
+        };
+
+        (
+            interpolate(self.angle_a_range, x, self.width),
+            interpolate(self.angle_b_range, y, self.height),
+        )
+    }
+}
+
+impl Serialize for FlipTimeSettings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FlipTimeSettings::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FlipTimeSettings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let settings = FlipTimeSettings::deserialize(deserializer)?;
+
+        if settings.step.is_zero() {
+            return Err(D::Error::custom("step must not be zero"));
+        }
+        if settings.steps_per_check == 0 {
+            return Err(D::Error::custom("steps_per_check must not be zero"));
+        }
+
+        Ok(settings)
+    }
+}
+
+fn has_flipped(configuration: &DoublePendulumConfiguration) -> bool {
+    configuration.a_configuration().flips() > 0 || configuration.b_configuration().flips() > 0
+}
+
+/// When every member of a flip-time grid first flipped
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FlipTimeMap {
+    settings: FlipTimeSettings,
+    /// Row by row, `angle_a` changing fastest and `angle_b` growing from row to row.
+    /// `None` if the member didn't flip within the maximum time.
+    flip_times: Vec<Option<f64>>,
+}
+
+impl FlipTimeMap {
+    pub fn settings(&self) -> &FlipTimeSettings {
+        &self.settings
+    }
+
+    pub fn flip_times(&self) -> &[Option<f64>] {
+        &self.flip_times
+    }
+
+    pub fn flip_time(&self, x: usize, y: usize) -> Option<f64> {
+        self.flip_times[y * self.settings.width + x]
+    }
+
+    /// The initial angles of the member at `(x, y)`, see [`FlipTimeSettings::angles`]
+    pub fn angles(&self, x: usize, y: usize) -> (f64, f64) {
+        self.settings.angles(x, y)
+    }
+
+    /// One `angle_a,angle_b,flip_time` line per member, with an empty flip time if it didn't flip
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(writer, "angle_a,angle_b,flip_time")?;
+
+        for y in 0..self.settings.height {
+            for x in 0..self.settings.width {
+                let (angle_a, angle_b) = self.angles(x, y);
+                match self.flip_time(x, y) {
+                    Some(flip_time) => writeln!(writer, "{},{},{}", angle_a, angle_b, flip_time)?,
+                    None => writeln!(writer, "{},{},", angle_a, angle_b)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flip_times() {
+    use crate::core::integrator::RungeKutta4;
+
+    let settings = FlipTimeSettings::new(8, 8, 5.0, Duration::from_secs_f64(0.01));
+    let generate = |settings: FlipTimeSettings| {
+        settings.generate(
+            &RungeKutta4,
+            Pendulum::new(1.0, 1.0),
+            Pendulum::new(1.0, 1.0),
+            Environment::new(9.81, 0.0, 1.0),
+        )
+    };
+    let map = generate(settings);
+
+    // Released close to hanging straight down, there isn't enough energy to ever flip
+    assert_eq!(map.angles(4, 4), (0.0, 0.0));
+    assert_eq!(map.flip_time(4, 4), None);
+    assert_eq!(map.flip_time(5, 4), None);
+    // Released upside down with the second arm sideways, it falls over immediately
+    assert!(map.flip_time(0, 6).unwrap() < 2.0);
+    assert!(map
+        .flip_times()
+        .iter()
+        .flatten()
+        .all(|&flip_time| flip_time <= settings.max_time()));
+
+    // Checking less often doesn't make flips any later, even with batches running past the maximum time
+    let batched = generate(settings.with_steps_per_check(7));
+    for (batched, single) in batched.flip_times().iter().zip(map.flip_times()) {
+        match (batched, single) {
+            (Some(batched), Some(single)) => assert!((batched - single).abs() < 1e-9),
+            _ => assert_eq!(batched, single),
+        }
+    }
+    assert!(std::panic::catch_unwind(|| settings.with_steps_per_check(0)).is_err());
+    assert!(std::panic::catch_unwind(|| FlipTimeSettings::new(8, 8, 5.0, Duration::ZERO)).is_err());
+
+    let mut csv = Vec::new();
+    map.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count(), 1 + 64);
+    assert!(csv.lines().nth(1 + 4 * 8 + 4).unwrap().ends_with(','));
+    // Half-open, -PI is in but PI isn't
+    assert_eq!(map.angles(0, 0), (-PI, -PI));
+    assert_eq!(map.angles(7, 7), (0.75 * PI, 0.75 * PI));
+}
@@ -0,0 +1,251 @@
+use crate::core::environment::Environment;
+use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+use serde::{Deserialize, Serialize};
+
+/// A double pendulum state in canonical coordinates,
+/// the angles together with their conjugate momenta.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CanonicalConfiguration {
+    /// Radians
+    angle_a: f64,
+    /// Radians
+    angle_b: f64,
+    /// Mass * length^2 / \[time\]
+    momentum_a: f64,
+    /// Mass * length^2 / \[time\]
+    momentum_b: f64,
+}
+
+impl CanonicalConfiguration {
+    pub fn new(angle_a: f64, angle_b: f64, momentum_a: f64, momentum_b: f64) -> Self {
+        CanonicalConfiguration {
+            angle_a,
+            angle_b,
+            momentum_a,
+            momentum_b,
+        }
+    }
+
+    pub fn from_configuration(
+        configuration: &DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+    ) -> Self {
+        let angle_a = configuration.a.angle;
+        let angle_b = configuration.b.angle;
+        let [[m_aa, m_ab], [m_ba, m_bb]] = mass_matrix(angle_a, angle_b, pendulum_a, pendulum_b);
+        let ang_vel_a = configuration.a.angular_velocity;
+        let ang_vel_b = configuration.b.angular_velocity;
+
+        CanonicalConfiguration {
+            angle_a,
+            angle_b,
+            momentum_a: m_aa * ang_vel_a + m_ab * ang_vel_b,
+            momentum_b: m_ba * ang_vel_a + m_bb * ang_vel_b,
+        }
+    }
+
+    pub fn to_configuration(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+    ) -> DoublePendulumConfiguration {
+        let (ang_vel_a, ang_vel_b) = self.angular_velocities(pendulum_a, pendulum_b);
+
+        DoublePendulumConfiguration::new(
+            PendulumConfiguration::new(self.angle_a, ang_vel_a),
+            PendulumConfiguration::new(self.angle_b, ang_vel_b),
+        )
+    }
+
+    pub fn angle_a(&self) -> f64 {
+        self.angle_a
+    }
+
+    pub fn angle_b(&self) -> f64 {
+        self.angle_b
+    }
+
+    pub fn momentum_a(&self) -> f64 {
+        self.momentum_a
+    }
+
+    pub fn momentum_b(&self) -> f64 {
+        self.momentum_b
+    }
+
+    /// Solves `momenta = M * angular_velocities` for the angular velocities, this is also dH/dp
+    pub fn angular_velocities(&self, pendulum_a: &Pendulum, pendulum_b: &Pendulum) -> (f64, f64) {
+        solve_linear(
+            mass_matrix(self.angle_a, self.angle_b, pendulum_a, pendulum_b),
+            (self.momentum_a, self.momentum_b),
+        )
+    }
+
+    /// dH/dq, the negative time derivative of the momenta
+    pub fn angle_gradient(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+    ) -> (f64, f64) {
+        let (ang_vel_a, ang_vel_b) = self.angular_velocities(pendulum_a, pendulum_b);
+        let gravity = environment.gravity();
+        let gravity_direction = environment.gravity_direction();
+        let coupling = pendulum_b.mass
+            * pendulum_a.length
+            * pendulum_b.length
+            * f64::sin(self.angle_a - self.angle_b)
+            * ang_vel_a
+            * ang_vel_b;
+
+        (
+            coupling
+                + (pendulum_a.mass + pendulum_b.mass)
+                    * gravity
+                    * pendulum_a.length
+                    * f64::sin(self.angle_a - gravity_direction),
+            -coupling
+                + pendulum_b.mass
+                    * gravity
+                    * pendulum_b.length
+                    * f64::sin(self.angle_b - gravity_direction),
+        )
+    }
+
+    /// Time derivative of the momenta, `-dH/dq` plus the generalized forces of damping and driving
+    pub fn momentum_derivative(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> (f64, f64) {
+        let (grad_a, grad_b) = self.angle_gradient(pendulum_a, pendulum_b, environment);
+        let (force_a, force_b) = self
+            .to_configuration(pendulum_a, pendulum_b)
+            .generalized_forces(pendulum_a, pendulum_b, environment, time);
+
+        (force_a - grad_a, force_b - grad_b)
+    }
+
+    /// The value of the Hamiltonian, i.e. the total energy
+    pub fn hamiltonian(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+    ) -> f64 {
+        let (ang_vel_a, ang_vel_b) = self.angular_velocities(pendulum_a, pendulum_b);
+        let kinetic = 0.5 * (self.momentum_a * ang_vel_a + self.momentum_b * ang_vel_b);
+        let potential = self
+            .to_configuration(pendulum_a, pendulum_b)
+            .potential_energy(pendulum_a, pendulum_b, environment);
+
+        kinetic + potential
+    }
+
+    /// Moves the angles by `h` times `angle_derivative` and the momenta by `h` times `momentum_derivative`
+    pub(crate) fn offset(
+        &self,
+        angle_derivative: (f64, f64),
+        momentum_derivative: (f64, f64),
+        h: f64,
+    ) -> Self {
+        CanonicalConfiguration {
+            angle_a: self.angle_a + angle_derivative.0 * h,
+            angle_b: self.angle_b + angle_derivative.1 * h,
+            momentum_a: self.momentum_a + momentum_derivative.0 * h,
+            momentum_b: self.momentum_b + momentum_derivative.1 * h,
+        }
+    }
+
+    pub(crate) fn set_angles(&mut self, (angle_a, angle_b): (f64, f64)) {
+        self.angle_a = angle_a;
+        self.angle_b = angle_b;
+    }
+
+    pub(crate) fn set_momenta(&mut self, (momentum_a, momentum_b): (f64, f64)) {
+        self.momentum_a = momentum_a;
+        self.momentum_b = momentum_b;
+    }
+}
+
+/// The (symmetric) mass matrix M with `T = 1/2 * ω^T * M * ω`
+pub fn mass_matrix(
+    angle_a: f64,
+    angle_b: f64,
+    pendulum_a: &Pendulum,
+    pendulum_b: &Pendulum,
+) -> [[f64; 2]; 2] {
+    let coupling =
+        pendulum_b.mass * pendulum_a.length * pendulum_b.length * f64::cos(angle_a - angle_b);
+
+    [
+        [
+            (pendulum_a.mass + pendulum_b.mass) * pendulum_a.length * pendulum_a.length,
+            coupling,
+        ],
+        [
+            coupling,
+            pendulum_b.mass * pendulum_b.length * pendulum_b.length,
+        ],
+    ]
+}
+
+/// Solves `matrix * x = rhs` for x
+pub fn solve_linear(
+    [[m_aa, m_ab], [m_ba, m_bb]]: [[f64; 2]; 2],
+    (rhs_a, rhs_b): (f64, f64),
+) -> (f64, f64) {
+    let determinant = m_aa * m_bb - m_ab * m_ba;
+
+    (
+        (m_bb * rhs_a - m_ab * rhs_b) / determinant,
+        (m_aa * rhs_b - m_ba * rhs_a) / determinant,
+    )
+}
+
+#[test]
+fn test_canonical_round_trip() {
+    let pendulum_a = Pendulum::new(180.0, 10.0);
+    let pendulum_b = Pendulum::new(162.0, 1.0);
+    let configuration = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.0, -0.7),
+        PendulumConfiguration::new(-1.0, 1.3),
+    );
+
+    let canonical =
+        CanonicalConfiguration::from_configuration(&configuration, &pendulum_a, &pendulum_b);
+    let round_trip = canonical.to_configuration(&pendulum_a, &pendulum_b);
+
+    assert!((round_trip.a.angular_velocity - -0.7).abs() < 1e-12);
+    assert!((round_trip.b.angular_velocity - 1.3).abs() < 1e-12);
+
+    // Hamilton's equations have to agree with the Lagrangian equations of motion
+    let h = 1e-6;
+    let environment = Environment::new(9.81, 0.3, 1.0);
+    let (grad_a, grad_b) = canonical.angle_gradient(&pendulum_a, &pendulum_b, &environment);
+    let moved = configuration.offset(
+        &configuration.derivative(&pendulum_a, &pendulum_b, &environment, 0.0),
+        h,
+    );
+    let moved_canonical =
+        CanonicalConfiguration::from_configuration(&moved, &pendulum_a, &pendulum_b);
+
+    let momentum_rate_a = (moved_canonical.momentum_a - canonical.momentum_a) / h;
+    let momentum_rate_b = (moved_canonical.momentum_b - canonical.momentum_b) / h;
+
+    assert!(
+        (momentum_rate_a + grad_a).abs() < 1e-3 * grad_a.abs().max(1.0),
+        "{} {}",
+        momentum_rate_a,
+        grad_a
+    );
+    assert!(
+        (momentum_rate_b + grad_b).abs() < 1e-3 * grad_b.abs().max(1.0),
+        "{} {}",
+        momentum_rate_b,
+        grad_b
+    );
+}
@@ -0,0 +1,778 @@
+use crate::core::environment::Environment;
+use crate::core::hamiltonian::CanonicalConfiguration;
+use crate::core::{DoublePendulumConfiguration, Pendulum};
+use std::time::Duration;
+
+/// A numerical scheme to advance a [`DoublePendulumConfiguration`] through time.
+pub trait Integrator {
+    fn step(
+        &self,
+        configuration: &mut DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    );
+}
+
+/// Updates the angular velocities first and then moves the angles with the new velocities.
+/// Cheap, but needs tiny steps to not drift away.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SemiImplicitEuler;
+
+impl Integrator for SemiImplicitEuler {
+    fn step(
+        &self,
+        configuration: &mut DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        let (ang_acc_a, ang_acc_b) =
+            configuration.angular_accelerations(pendulum_a, pendulum_b, environment, time);
+        let secs = duration.as_secs_f64();
+
+        configuration.a.angular_velocity += ang_acc_a * secs;
+        configuration.b.angular_velocity += ang_acc_b * secs;
+        configuration.a.angle += configuration.a.angular_velocity * secs;
+        configuration.b.angle += configuration.b.angular_velocity * secs;
+
+        configuration.wrap_angles();
+    }
+}
+
+/// Classic fourth order Runge-Kutta.
+/// Four evaluations of the equations of motion per step, but a lot more accurate than Euler.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct RungeKutta4;
+
+impl Integrator for RungeKutta4 {
+    fn step(
+        &self,
+        configuration: &mut DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        let h = duration.as_secs_f64();
+
+        let k1 = configuration.derivative(pendulum_a, pendulum_b, environment, time);
+        let k2 = configuration.offset(&k1, h / 2.0).derivative(
+            pendulum_a,
+            pendulum_b,
+            environment,
+            time + h / 2.0,
+        );
+        let k3 = configuration.offset(&k2, h / 2.0).derivative(
+            pendulum_a,
+            pendulum_b,
+            environment,
+            time + h / 2.0,
+        );
+        let k4 =
+            configuration
+                .offset(&k3, h)
+                .derivative(pendulum_a, pendulum_b, environment, time + h);
+
+        let weighted = weighted_sum(
+            &[k1, k2, k3, k4],
+            &[1.0 / 6.0, 2.0 / 6.0, 2.0 / 6.0, 1.0 / 6.0],
+        );
+
+        *configuration = configuration.offset(&weighted, h);
+
+        configuration.wrap_angles();
+    }
+}
+
+/// Embedded Runge-Kutta 5(4) pair by Dormand and Prince with adaptive step size control.
+/// Every [`Integrator::step`] call is split into as many substeps as the tolerances require for
+/// that configuration, so calm configurations take few large steps and violent ones many small
+/// steps, but all of them end up at the same time.
+/// Steps panic if the state stops being finite or the tolerances can't be met,
+/// instead of crawling on with ever smaller substeps.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DormandPrince45 {
+    absolute_tolerance: f64,
+    relative_tolerance: f64,
+}
+
+impl DormandPrince45 {
+    const C: [f64; 6] = [1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+    const A: [&'static [f64]; 6] = [
+        &[1.0 / 5.0],
+        &[3.0 / 40.0, 9.0 / 40.0],
+        &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+        &[
+            19372.0 / 6561.0,
+            -25360.0 / 2187.0,
+            64448.0 / 6561.0,
+            -212.0 / 729.0,
+        ],
+        &[
+            9017.0 / 3168.0,
+            -355.0 / 33.0,
+            46732.0 / 5247.0,
+            49.0 / 176.0,
+            -5103.0 / 18656.0,
+        ],
+        &[
+            35.0 / 384.0,
+            0.0,
+            500.0 / 1113.0,
+            125.0 / 192.0,
+            -2187.0 / 6784.0,
+            11.0 / 84.0,
+        ],
+    ];
+    /// Difference between the fifth and fourth order weights
+    const E: [f64; 7] = [
+        71.0 / 57600.0,
+        0.0,
+        -71.0 / 16695.0,
+        71.0 / 1920.0,
+        -17253.0 / 339200.0,
+        22.0 / 525.0,
+        -1.0 / 40.0,
+    ];
+    /// Integration fails if the error estimate asks for steps smaller than this \[time\]
+    const MINIMUM_STEP: f64 = 1e-12;
+
+    pub fn new(absolute_tolerance: f64, relative_tolerance: f64) -> Self {
+        DormandPrince45 {
+            absolute_tolerance,
+            relative_tolerance,
+        }
+    }
+
+    pub fn absolute_tolerance(&self) -> f64 {
+        self.absolute_tolerance
+    }
+
+    pub fn relative_tolerance(&self) -> f64 {
+        self.relative_tolerance
+    }
+
+    /// Integrates `state` from `time` over `duration` \[time\] with adaptive steps,
+    /// `derivative` writes the time derivative of a state at a time into its last argument.
+    /// `step_size` is where to start, the whole duration if unset, and afterwards where to pick up next time.
+    ///
+    /// # Errors
+    /// If a step doesn't give a finite state and error estimate, or doesn't meet the tolerances even at the minimum step size.
+    /// `state` is left where the last accepted step ended.
+    pub(crate) fn integrate(
+        &self,
+        state: &mut [f64],
+        step_size: &mut Option<f64>,
+        time: f64,
+        duration: f64,
+        derivative: impl Fn(&[f64], f64, &mut [f64]),
+    ) -> Result<(), String> {
+        let mut ks = vec![vec![0.0; state.len()]; 7];
+        let mut new_state = vec![0.0; state.len()];
+        let mut time = time;
+        let mut remaining = duration;
+        // The step size that worked last time is a good guess
+        let mut h = step_size.filter(|&h| h > 0.0).unwrap_or(remaining);
+        derivative(state, time, &mut ks[0]);
+
+        while remaining > 0.0 {
+            // The last substep is cut short to end exactly at the duration, that doesn't change the step size to remember
+            let proposed = h;
+            let last = h >= remaining;
+            if last {
+                h = remaining;
+            }
+
+            let error_norm = self.attempt(state, &mut ks, &mut new_state, time, h, &derivative);
+            if !error_norm.is_finite() || new_state.iter().any(|value| !value.is_finite()) {
+                return Err(format!("The state stopped being finite at time {}", time));
+            }
+
+            // Standard step size controller with a safety factor, limiting how quickly h changes
+            let factor = if error_norm == 0.0 {
+                5.0
+            } else {
+                (0.9 * error_norm.powf(-1.0 / 5.0)).clamp(0.2, 5.0)
+            };
+
+            if error_norm <= 1.0 {
+                state.copy_from_slice(&new_state);
+                // First same as last, the derivative at the end is the next derivative at the start
+                ks.swap(0, 6);
+                time += h;
+                remaining = if last { 0.0 } else { remaining - h };
+                h = if last {
+                    f64::max(proposed, h * factor.max(1.0))
+                } else {
+                    h * factor.max(1.0)
+                };
+            } else if h <= Self::MINIMUM_STEP {
+                return Err(format!(
+                    "The tolerances can't be met at time {} even with the minimum step size {}",
+                    time,
+                    Self::MINIMUM_STEP
+                ));
+            } else {
+                h = f64::max(h * factor, Self::MINIMUM_STEP);
+            }
+        }
+
+        if h > 0.0 {
+            *step_size = Some(h);
+        }
+
+        Ok(())
+    }
+
+    /// Attempts a single step of size `h` from `state` at `time`, whose derivative is `ks[0]`.
+    /// Fills in the other stage derivatives, writes the new state into `new_state`
+    /// and returns the scaled error norm (the step should be accepted if that is at most 1).
+    fn attempt(
+        &self,
+        state: &[f64],
+        ks: &mut [Vec<f64>],
+        new_state: &mut [f64],
+        time: f64,
+        h: f64,
+        derivative: &impl Fn(&[f64], f64, &mut [f64]),
+    ) -> f64 {
+        // The last stage is the new state itself
+        for (stage, (a, c)) in Self::A.iter().zip(Self::C).enumerate() {
+            for (i, value) in new_state.iter_mut().enumerate() {
+                let change: f64 = a.iter().zip(&*ks).map(|(a, k)| a * k[i]).sum();
+                *value = state[i] + change * h;
+            }
+            derivative(new_state, time + c * h, &mut ks[stage + 1]);
+        }
+
+        let error_norm = (0..state.len())
+            .map(|i| {
+                let error: f64 = Self::E.iter().zip(&*ks).map(|(e, k)| e * k[i]).sum();
+                let scale = self.absolute_tolerance
+                    + self.relative_tolerance * f64::max(state[i].abs(), new_state[i].abs());
+                let scaled = error * h / scale;
+                scaled * scaled
+            })
+            .sum::<f64>()
+            / state.len() as f64;
+
+        error_norm.sqrt()
+    }
+}
+
+impl Default for DormandPrince45 {
+    fn default() -> Self {
+        DormandPrince45::new(1e-9, 1e-9)
+    }
+}
+
+impl Integrator for DormandPrince45 {
+    fn step(
+        &self,
+        configuration: &mut DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        let start = *configuration;
+        let mut state = configuration.state();
+
+        self.integrate(
+            &mut state,
+            &mut configuration.adaptive_step,
+            time,
+            duration.as_secs_f64(),
+            |state, time, derivative| {
+                derivative.copy_from_slice(&start.with_state(state).derivative(
+                    pendulum_a,
+                    pendulum_b,
+                    environment,
+                    time,
+                ))
+            },
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+
+        *configuration = configuration.with_state(&state);
+        configuration.wrap_angles();
+    }
+}
+
+/// An integrator working on the Hamiltonian formulation of the double pendulum.
+/// Symplectic integrators don't accumulate energy errors over time,
+/// the energy just oscillates in a bounded band around the true value.
+/// Damping is added to the momentum updates, which of course loses that property.
+pub trait SymplecticIntegrator {
+    /// Advances `configuration` from `time` by `h` \[time\], which may be negative
+    fn step_canonical(
+        &self,
+        configuration: &mut CanonicalConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        h: f64,
+    );
+}
+
+/// Solving the implicit equations of the symplectic integrators is done by fixed point iteration,
+/// this many iterations at most
+const MAX_FIXED_POINT_ITERATIONS: u32 = 50;
+/// Fixed point iteration stops early once the iterates change less than this
+const FIXED_POINT_TOLERANCE: f64 = 1e-14;
+
+fn fixed_point(initial: (f64, f64), f: impl Fn((f64, f64)) -> (f64, f64)) -> (f64, f64) {
+    let mut current = initial;
+
+    for _ in 0..MAX_FIXED_POINT_ITERATIONS {
+        let next = f(current);
+        let change = f64::max((next.0 - current.0).abs(), (next.1 - current.1).abs());
+        let scale = f64::max(1.0, f64::max(next.0.abs(), next.1.abs()));
+        current = next;
+
+        if change <= FIXED_POINT_TOLERANCE * scale {
+            break;
+        }
+    }
+
+    current
+}
+
+fn step_symplectic(
+    integrator: &impl SymplecticIntegrator,
+    configuration: &mut DoublePendulumConfiguration,
+    pendulum_a: &Pendulum,
+    pendulum_b: &Pendulum,
+    environment: &Environment,
+    time: f64,
+    duration: Duration,
+) {
+    let mut canonical =
+        CanonicalConfiguration::from_configuration(configuration, pendulum_a, pendulum_b);
+    integrator.step_canonical(
+        &mut canonical,
+        pendulum_a,
+        pendulum_b,
+        environment,
+        time,
+        duration.as_secs_f64(),
+    );
+    // Only the angles and angular velocities, the turn counters carry over
+    let stepped = canonical.to_configuration(pendulum_a, pendulum_b);
+    configuration.a.angle = stepped.a.angle;
+    configuration.a.angular_velocity = stepped.a.angular_velocity;
+    configuration.b.angle = stepped.b.angle;
+    configuration.b.angular_velocity = stepped.b.angular_velocity;
+
+    configuration.wrap_angles();
+}
+
+/// Generalized Störmer-Verlet (leapfrog) for the non-separable double pendulum Hamiltonian.
+/// Second order, symmetric and symplectic.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct StormerVerlet;
+
+impl SymplecticIntegrator for StormerVerlet {
+    fn step_canonical(
+        &self,
+        configuration: &mut CanonicalConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        h: f64,
+    ) {
+        let start = *configuration;
+        let half_h = h / 2.0;
+
+        // Half kick, implicit in the new momenta
+        let half_momenta = fixed_point((start.momentum_a(), start.momentum_b()), |momenta| {
+            let mut trial = start;
+            trial.set_momenta(momenta);
+            let (rate_a, rate_b) =
+                trial.momentum_derivative(pendulum_a, pendulum_b, environment, time);
+            (
+                start.momentum_a() + half_h * rate_a,
+                start.momentum_b() + half_h * rate_b,
+            )
+        });
+        let mut half = start;
+        half.set_momenta(half_momenta);
+
+        // Drift, implicit in the new angles
+        let (start_vel_a, start_vel_b) = half.angular_velocities(pendulum_a, pendulum_b);
+        let angles = fixed_point(
+            (
+                start.angle_a() + h * start_vel_a,
+                start.angle_b() + h * start_vel_b,
+            ),
+            |angles| {
+                let mut trial = half;
+                trial.set_angles(angles);
+                let (vel_a, vel_b) = trial.angular_velocities(pendulum_a, pendulum_b);
+                (
+                    start.angle_a() + half_h * (start_vel_a + vel_a),
+                    start.angle_b() + half_h * (start_vel_b + vel_b),
+                )
+            },
+        );
+        half.set_angles(angles);
+
+        // Explicit half kick
+        let momentum_rates =
+            half.momentum_derivative(pendulum_a, pendulum_b, environment, time + h);
+        *configuration = half.offset((0.0, 0.0), momentum_rates, half_h);
+    }
+}
+
+impl Integrator for StormerVerlet {
+    fn step(
+        &self,
+        configuration: &mut DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        step_symplectic(
+            self,
+            configuration,
+            pendulum_a,
+            pendulum_b,
+            environment,
+            time,
+            duration,
+        );
+    }
+}
+
+/// The implicit midpoint rule, second order, symmetric and symplectic.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ImplicitMidpoint;
+
+impl SymplecticIntegrator for ImplicitMidpoint {
+    fn step_canonical(
+        &self,
+        configuration: &mut CanonicalConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        h: f64,
+    ) {
+        let start = *configuration;
+        let half_h = h / 2.0;
+
+        // Iterate on the midpoint itself: midpoint = start + h/2 * f(midpoint)
+        let mut midpoint = start;
+        for _ in 0..MAX_FIXED_POINT_ITERATIONS {
+            let velocities = midpoint.angular_velocities(pendulum_a, pendulum_b);
+            let momentum_rates =
+                midpoint.momentum_derivative(pendulum_a, pendulum_b, environment, time + half_h);
+            let next = start.offset(velocities, momentum_rates, half_h);
+
+            let change = [
+                next.angle_a() - midpoint.angle_a(),
+                next.angle_b() - midpoint.angle_b(),
+                next.momentum_a() - midpoint.momentum_a(),
+                next.momentum_b() - midpoint.momentum_b(),
+            ]
+            .iter()
+            .fold(0.0, |max: f64, change| max.max(change.abs()));
+            let scale = [next.momentum_a(), next.momentum_b(), 1.0]
+                .iter()
+                .fold(0.0, |max: f64, value| max.max(value.abs()));
+            midpoint = next;
+
+            if change <= FIXED_POINT_TOLERANCE * scale {
+                break;
+            }
+        }
+
+        // end = 2 * midpoint - start
+        *configuration = CanonicalConfiguration::new(
+            2.0 * midpoint.angle_a() - start.angle_a(),
+            2.0 * midpoint.angle_b() - start.angle_b(),
+            2.0 * midpoint.momentum_a() - start.momentum_a(),
+            2.0 * midpoint.momentum_b() - start.momentum_b(),
+        );
+    }
+}
+
+impl Integrator for ImplicitMidpoint {
+    fn step(
+        &self,
+        configuration: &mut DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        step_symplectic(
+            self,
+            configuration,
+            pendulum_a,
+            pendulum_b,
+            environment,
+            time,
+            duration,
+        );
+    }
+}
+
+/// Yoshida's fourth order composition of a symmetric second order method:
+/// three substeps with weights `w1, w0, w1`, where the middle one goes backwards in time.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Yoshida4<B = StormerVerlet> {
+    base: B,
+}
+
+impl<B: SymplecticIntegrator> Yoshida4<B> {
+    pub fn new(base: B) -> Self {
+        Yoshida4 { base }
+    }
+
+    pub fn base(&self) -> &B {
+        &self.base
+    }
+
+    fn weights() -> (f64, f64) {
+        let cbrt_two = 2.0f64.cbrt();
+        let w1 = 1.0 / (2.0 - cbrt_two);
+        let w0 = -cbrt_two / (2.0 - cbrt_two);
+
+        (w1, w0)
+    }
+}
+
+impl<B: SymplecticIntegrator> SymplecticIntegrator for Yoshida4<B> {
+    fn step_canonical(
+        &self,
+        configuration: &mut CanonicalConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        h: f64,
+    ) {
+        let (w1, w0) = Self::weights();
+        let mut time = time;
+
+        for w in [w1, w0, w1] {
+            self.base.step_canonical(
+                configuration,
+                pendulum_a,
+                pendulum_b,
+                environment,
+                time,
+                w * h,
+            );
+            time += w * h;
+        }
+    }
+}
+
+impl<B: SymplecticIntegrator> Integrator for Yoshida4<B> {
+    fn step(
+        &self,
+        configuration: &mut DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        step_symplectic(
+            self,
+            configuration,
+            pendulum_a,
+            pendulum_b,
+            environment,
+            time,
+            duration,
+        );
+    }
+}
+
+/// `sum(coefficients[i] * vectors[i])`, ignoring vectors without a coefficient
+fn weighted_sum(vectors: &[[f64; 4]], coefficients: &[f64]) -> [f64; 4] {
+    let mut sum = [0.0; 4];
+
+    for (vector, coefficient) in vectors.iter().zip(coefficients) {
+        for (sum, component) in sum.iter_mut().zip(vector) {
+            *sum += coefficient * component;
+        }
+    }
+
+    sum
+}
+
+#[test]
+fn test_rk4_converges() {
+    use crate::core::util::normalize_angle;
+    use crate::core::PendulumConfiguration;
+
+    let pendulum_a = Pendulum::new(1.0, 2.0);
+    let pendulum_b = Pendulum::new(1.5, 1.0);
+    let initial = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(1.0, 0.0),
+        PendulumConfiguration::new(-0.5, 0.5),
+    );
+
+    let run = |integrator: &dyn Integrator, steps: u32| {
+        let mut configuration = initial;
+        let step = Duration::from_secs_f64(0.2 / steps as f64);
+        for _ in 0..steps {
+            integrator.step(
+                &mut configuration,
+                &pendulum_a,
+                &pendulum_b,
+                &Environment::default(),
+                0.0,
+                step,
+            );
+        }
+        configuration
+    };
+
+    let reference = run(&RungeKutta4, 4_000);
+    let error = |configuration: DoublePendulumConfiguration| {
+        normalize_angle(configuration.a.angle - reference.a.angle).abs()
+            + normalize_angle(configuration.b.angle - reference.b.angle).abs()
+    };
+
+    let rk4_error = error(run(&RungeKutta4, 40));
+    let euler_error = error(run(&SemiImplicitEuler, 40));
+
+    assert!(rk4_error < 1e-6, "{}", rk4_error);
+    assert!(
+        rk4_error < euler_error / 100.0,
+        "{} {}",
+        rk4_error,
+        euler_error
+    );
+}
+
+#[test]
+fn test_dormand_prince_adapts() {
+    use crate::core::util::normalize_angle;
+    use crate::core::PendulumConfiguration;
+
+    let pendulum_a = Pendulum::new(1.0, 2.0);
+    let pendulum_b = Pendulum::new(1.5, 1.0);
+    let environment = Environment::default();
+    let initial = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(1.0, 0.0),
+        PendulumConfiguration::new(-0.5, 0.5),
+    );
+
+    let mut reference = initial;
+    for _ in 0..4_000 {
+        RungeKutta4.step(
+            &mut reference,
+            &pendulum_a,
+            &pendulum_b,
+            &environment,
+            0.0,
+            Duration::from_secs_f64(0.00005),
+        );
+    }
+
+    // A single call, the substeps are up to the integrator
+    let integrator = DormandPrince45::new(1e-11, 1e-11);
+    let mut adaptive = initial;
+    integrator.step(
+        &mut adaptive,
+        &pendulum_a,
+        &pendulum_b,
+        &environment,
+        0.0,
+        Duration::from_secs_f64(0.2),
+    );
+    let error = normalize_angle(adaptive.a.angle - reference.a.angle).abs()
+        + normalize_angle(adaptive.b.angle - reference.b.angle).abs();
+    assert!(error < 1e-8, "{}", error);
+
+    // The next call starts from the step size the tolerances asked for, not from the whole duration
+    let step_size = adaptive.adaptive_step.expect("remembered");
+    assert!(step_size < 0.2, "{}", step_size);
+    integrator.step(
+        &mut adaptive,
+        &pendulum_a,
+        &pendulum_b,
+        &environment,
+        0.2,
+        Duration::from_secs_f64(0.2),
+    );
+    assert!(adaptive.adaptive_step.expect("remembered") < 0.2);
+
+    // Failing steps stop the integration instead of being taken anyway at the minimum step size
+    let mut state = [1.0];
+    let result = integrator.integrate(&mut state, &mut None, 0.0, 1.0, |_, _, derivative| {
+        derivative[0] = f64::NAN
+    });
+    assert!(result.is_err());
+    assert_eq!(state, [1.0]);
+    let result = integrator.integrate(&mut state, &mut None, 0.0, 1.0, |_, time, derivative| {
+        derivative[0] = if time < 0.5 { 0.0 } else { 1e20 }
+    });
+    assert!(result.is_err());
+    assert_eq!(state, [1.0]);
+}
+
+#[test]
+fn test_symplectic_energy_bounded() {
+    use crate::core::PendulumConfiguration;
+
+    let pendulum_a = Pendulum::new(1.0, 2.0);
+    let pendulum_b = Pendulum::new(1.5, 1.0);
+    let initial = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.0, 0.0),
+        PendulumConfiguration::new(-1.0, 3.0),
+    );
+    let energy = |configuration: &DoublePendulumConfiguration| {
+        CanonicalConfiguration::from_configuration(configuration, &pendulum_a, &pendulum_b)
+            .hamiltonian(&pendulum_a, &pendulum_b, &Environment::default())
+    };
+    let initial_energy = energy(&initial);
+
+    let max_energy_error = |integrator: &dyn Integrator| {
+        let mut configuration = initial;
+        let mut max_error: f64 = 0.0;
+        for _ in 0..20_000 {
+            integrator.step(
+                &mut configuration,
+                &pendulum_a,
+                &pendulum_b,
+                &Environment::default(),
+                0.0,
+                Duration::from_secs_f64(0.001),
+            );
+            max_error = max_error.max((energy(&configuration) - initial_energy).abs());
+        }
+        max_error / initial_energy.abs()
+    };
+
+    let verlet_error = max_energy_error(&StormerVerlet);
+    let midpoint_error = max_energy_error(&ImplicitMidpoint);
+    let yoshida_error = max_energy_error(&Yoshida4::<StormerVerlet>::default());
+
+    assert!(verlet_error < 1e-2, "{}", verlet_error);
+    assert!(midpoint_error < 1e-2, "{}", midpoint_error);
+    assert!(
+        yoshida_error < verlet_error / 10.0,
+        "{} {}",
+        yoshida_error,
+        verlet_error
+    );
+}
@@ -0,0 +1,537 @@
+use crate::core::environment::Environment;
+use crate::core::integrator::Integrator;
+use crate::core::util::normalize_angle;
+use crate::core::{DoublePendulumCollection, DoublePendulumConfiguration, Pendulum};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How the growth of a small perturbation is followed
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum LyapunovMethod {
+    /// A second configuration `separation` away in state space, stepped with the same integrator
+    /// and pulled back to `separation` after every renormalization
+    Shadow { separation: f64 },
+    /// A tangent vector stepped with the linearized equations of motion along the trajectory
+    Variational,
+}
+
+/// Estimates the largest Lyapunov exponent, in 1 per \[time\].
+/// Distances are measured in `[angle_a, angle_b, angular_velocity_a, angular_velocity_b]` space.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LyapunovEstimator {
+    method: LyapunovMethod,
+    /// Step duration passed to the integrator, scaled with the environment's time scale
+    step: Duration,
+    steps_per_renormalization: u32,
+    renormalizations: u32,
+}
+
+impl LyapunovEstimator {
+    pub fn new(
+        method: LyapunovMethod,
+        step: Duration,
+        steps_per_renormalization: u32,
+        renormalizations: u32,
+    ) -> Self {
+        LyapunovEstimator {
+            method,
+            step,
+            steps_per_renormalization,
+            renormalizations,
+        }
+    }
+
+    pub fn method(&self) -> &LyapunovMethod {
+        &self.method
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    pub fn steps_per_renormalization(&self) -> u32 {
+        self.steps_per_renormalization
+    }
+
+    pub fn renormalizations(&self) -> u32 {
+        self.renormalizations
+    }
+
+    /// Follows `configuration` from `time` on
+    pub fn estimate(
+        &self,
+        integrator: &impl Integrator,
+        configuration: &DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> LyapunovEstimate {
+        let h = environment.scale(self.step).as_secs_f64();
+        let mut configuration = *configuration;
+        let mut time = time;
+
+        // Away from every axis, so no direction is favoured
+        let initial_direction = [0.5; 4];
+        let (mut shadow, mut tangent) = match self.method {
+            LyapunovMethod::Shadow { separation } => (
+                Some(configuration.offset(&initial_direction, separation)),
+                initial_direction,
+            ),
+            LyapunovMethod::Variational => (None, initial_direction),
+        };
+
+        let mut log_growth_sum = 0.0;
+        let mut elapsed = 0.0;
+        let mut convergence = Vec::with_capacity(self.renormalizations as usize);
+
+        for _ in 0..self.renormalizations {
+            let interval_start = elapsed;
+
+            for _ in 0..self.steps_per_renormalization {
+                let previous = configuration;
+                configuration.step(
+                    integrator,
+                    pendulum_a,
+                    pendulum_b,
+                    environment,
+                    time,
+                    self.step,
+                );
+
+                match &mut shadow {
+                    Some(shadow) => shadow.step(
+                        integrator,
+                        pendulum_a,
+                        pendulum_b,
+                        environment,
+                        time,
+                        self.step,
+                    ),
+                    None => step_tangents(
+                        std::slice::from_mut(&mut tangent),
+                        &previous,
+                        &configuration,
+                        pendulum_a,
+                        pendulum_b,
+                        environment,
+                        time,
+                        h,
+                    ),
+                }
+
+                time += h;
+                elapsed += h;
+            }
+
+            let growth = match (&mut shadow, self.method) {
+                (Some(shadow), LyapunovMethod::Shadow { separation }) => {
+                    let difference = state_difference(shadow, &configuration);
+                    let distance = norm(&difference);
+                    if !(distance > 0.0 && distance.is_finite()) {
+                        // The shadow fell onto the trajectory or ran off, so there is no direction to pull it back along.
+                        // The interval doesn't count and the shadow starts over like at the beginning.
+                        *shadow = configuration.offset(&initial_direction, separation);
+                        elapsed = interval_start;
+                        continue;
+                    }
+                    *shadow = configuration.offset(&difference, separation / distance);
+
+                    distance / separation
+                }
+                _ => {
+                    let length = norm(&tangent);
+                    if !(length > 0.0 && length.is_finite()) {
+                        // Same as for the shadow, the tangent has no direction left to normalize
+                        tangent = initial_direction;
+                        elapsed = interval_start;
+                        continue;
+                    }
+                    tangent.iter_mut().for_each(|x| *x /= length);
+
+                    length
+                }
+            };
+
+            log_growth_sum += growth.ln();
+            convergence.push((elapsed, log_growth_sum / elapsed));
+        }
+
+        LyapunovEstimate {
+            exponent: convergence.last().map_or(0.0, |&(_, exponent)| exponent),
+            convergence,
+        }
+    }
+
+    /// All four exponents, largest first, from a tangent basis re-orthonormalized by QR decomposition
+    /// at every renormalization. The method is ignored, this always uses the tangent equations.
+    pub fn spectrum(
+        &self,
+        integrator: &impl Integrator,
+        configuration: &DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> LyapunovSpectrum {
+        let h = environment.scale(self.step).as_secs_f64();
+        let mut configuration = *configuration;
+        let mut time = time;
+
+        let mut basis = IDENTITY;
+
+        let mut log_growth_sums = [0.0; 4];
+        let mut elapsed = 0.0;
+        let mut convergence = Vec::with_capacity(self.renormalizations as usize);
+
+        for _ in 0..self.renormalizations {
+            let interval_start = elapsed;
+
+            for _ in 0..self.steps_per_renormalization {
+                let previous = configuration;
+                configuration.step(
+                    integrator,
+                    pendulum_a,
+                    pendulum_b,
+                    environment,
+                    time,
+                    self.step,
+                );
+                step_tangents(
+                    &mut basis,
+                    &previous,
+                    &configuration,
+                    pendulum_a,
+                    pendulum_b,
+                    environment,
+                    time,
+                    h,
+                );
+
+                time += h;
+                elapsed += h;
+            }
+
+            let growths = match orthonormalize(&mut basis) {
+                Some(growths) => growths,
+                None => {
+                    // Some tangents collapsed onto the others or ran off, so their growth can't be told apart.
+                    // The interval doesn't count and the basis starts over like at the beginning.
+                    basis = IDENTITY;
+                    elapsed = interval_start;
+                    continue;
+                }
+            };
+            for (sum, growth) in log_growth_sums.iter_mut().zip(growths) {
+                *sum += growth.ln();
+            }
+            convergence.push((elapsed, log_growth_sums.map(|sum| sum / elapsed)));
+        }
+
+        LyapunovSpectrum {
+            exponents: convergence
+                .last()
+                .map_or([0.0; 4], |&(_, exponents)| exponents),
+            convergence,
+        }
+    }
+
+    /// Estimates every member of `collection` in parallel, from the collection's current time on
+    pub fn estimate_all(
+        &self,
+        integrator: &(impl Integrator + Sync),
+        collection: &DoublePendulumCollection,
+    ) -> Vec<LyapunovEstimate> {
+        collection
+            .pendulum_configurations()
+            .par_iter()
+            .enumerate()
+            .map(|(i, configuration)| {
+                let (pendulum_a, pendulum_b) = collection.member_pendulums(i);
+                self.estimate(
+                    integrator,
+                    configuration,
+                    pendulum_a,
+                    pendulum_b,
+                    collection.environment(),
+                    collection.time(),
+                )
+            })
+            .collect()
+    }
+
+    /// [`spectrum`](Self::spectrum) of every member of `collection` in parallel, from the collection's current time on
+    pub fn spectrum_all(
+        &self,
+        integrator: &(impl Integrator + Sync),
+        collection: &DoublePendulumCollection,
+    ) -> Vec<LyapunovSpectrum> {
+        collection
+            .pendulum_configurations()
+            .par_iter()
+            .enumerate()
+            .map(|(i, configuration)| {
+                let (pendulum_a, pendulum_b) = collection.member_pendulums(i);
+                self.spectrum(
+                    integrator,
+                    configuration,
+                    pendulum_a,
+                    pendulum_b,
+                    collection.environment(),
+                    collection.time(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LyapunovEstimate {
+    /// The estimate after the last renormalization
+    exponent: f64,
+    /// `(elapsed [time], estimate)` after every renormalization
+    convergence: Vec<(f64, f64)>,
+}
+
+impl LyapunovEstimate {
+    pub fn exponent(&self) -> f64 {
+        self.exponent
+    }
+
+    pub fn convergence(&self) -> &[(f64, f64)] {
+        &self.convergence
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LyapunovSpectrum {
+    /// The estimates after the last renormalization, largest first
+    exponents: [f64; 4],
+    /// `(elapsed [time], estimates)` after every renormalization
+    convergence: Vec<(f64, [f64; 4])>,
+}
+
+impl LyapunovSpectrum {
+    pub fn exponents(&self) -> [f64; 4] {
+        self.exponents
+    }
+
+    pub fn convergence(&self) -> &[(f64, [f64; 4])] {
+        &self.convergence
+    }
+
+    /// Zero up to estimation error for conservative systems, which keep phase space volume
+    pub fn sum(&self) -> f64 {
+        self.exponents.iter().sum()
+    }
+}
+
+/// `a - b`, with angle differences taken the short way around
+pub(crate) fn state_difference(
+    a: &DoublePendulumConfiguration,
+    b: &DoublePendulumConfiguration,
+) -> [f64; 4] {
+    let (a, b) = (a.state(), b.state());
+
+    [
+        normalize_angle(a[0] - b[0]),
+        normalize_angle(a[1] - b[1]),
+        a[2] - b[2],
+        a[3] - b[3],
+    ]
+}
+
+fn norm(vector: &[f64; 4]) -> f64 {
+    vector.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+fn apply(matrix: &[[f64; 4]; 4], vector: &[f64; 4]) -> [f64; 4] {
+    matrix.map(|row| row.iter().zip(vector).map(|(m, v)| m * v).sum())
+}
+
+/// One RK4 step of the tangent equations `d tangent / dt = J tangent` for every tangent,
+/// with the Jacobian taken at the trajectory's states before, halfway through and after the step
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn step_tangents(
+    tangents: &mut [[f64; 4]],
+    previous: &DoublePendulumConfiguration,
+    next: &DoublePendulumConfiguration,
+    pendulum_a: &Pendulum,
+    pendulum_b: &Pendulum,
+    environment: &Environment,
+    time: f64,
+    h: f64,
+) {
+    let midpoint = previous.offset(&state_difference(next, previous), 0.5);
+
+    let jacobian_start = previous.jacobian(pendulum_a, pendulum_b, environment, time);
+    let jacobian_mid = midpoint.jacobian(pendulum_a, pendulum_b, environment, time + h / 2.0);
+    let jacobian_end = next.jacobian(pendulum_a, pendulum_b, environment, time + h);
+
+    for tangent in tangents {
+        let offset = |k: &[f64; 4], factor: f64| {
+            let mut result = *tangent;
+            result.iter_mut().zip(k).for_each(|(x, k)| *x += k * factor);
+            result
+        };
+
+        let k1 = apply(&jacobian_start, tangent);
+        let k2 = apply(&jacobian_mid, &offset(&k1, h / 2.0));
+        let k3 = apply(&jacobian_mid, &offset(&k2, h / 2.0));
+        let k4 = apply(&jacobian_end, &offset(&k3, h));
+
+        for i in 0..4 {
+            tangent[i] += h / 6.0 * (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i]);
+        }
+    }
+}
+
+const IDENTITY: [[f64; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Tangents whose length shrinks below this fraction when the earlier ones are projected out
+/// are taken to depend on them, their direction would be mostly rounding error
+const DEPENDENCE_TOLERANCE: f64 = 1e-12;
+
+/// Modified Gram-Schmidt, leaving `basis` orthonormal and returning the diagonal of R.
+/// `None` if the tangents are linearly dependent or not finite, `basis` is of no use then.
+fn orthonormalize(basis: &mut [[f64; 4]; 4]) -> Option<[f64; 4]> {
+    let mut diagonal = [0.0; 4];
+
+    for (i, length) in diagonal.iter_mut().enumerate() {
+        let (done, rest) = basis.split_at_mut(i);
+        let vector = &mut rest[0];
+        let original_length = norm(vector);
+
+        for previous in done.iter() {
+            let projection: f64 = previous.iter().zip(vector.iter()).map(|(p, v)| p * v).sum();
+            vector
+                .iter_mut()
+                .zip(previous)
+                .for_each(|(v, p)| *v -= projection * p);
+        }
+
+        *length = norm(vector);
+        if !(*length > DEPENDENCE_TOLERANCE * original_length && original_length.is_finite()) {
+            return None;
+        }
+        vector.iter_mut().for_each(|v| *v /= *length);
+    }
+
+    Some(diagonal)
+}
+
+#[test]
+fn test_lyapunov_methods_agree() {
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::PendulumConfiguration;
+
+    let pendulum_a = Pendulum::new(1.0, 1.0);
+    let pendulum_b = Pendulum::new(1.0, 1.0);
+    let environment = Environment::new(9.81, 0.0, 1.0);
+    let step = Duration::from_secs_f64(0.005);
+
+    let chaotic = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.5, 0.0),
+        PendulumConfiguration::new(-2.0, 0.0),
+    );
+    let regular = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(0.05, 0.0),
+        PendulumConfiguration::new(0.05, 0.0),
+    );
+
+    let estimate = |method, configuration| {
+        LyapunovEstimator::new(method, step, 20, 300)
+            .estimate(
+                &RungeKutta4,
+                &configuration,
+                &pendulum_a,
+                &pendulum_b,
+                &environment,
+                0.0,
+            )
+            .exponent()
+    };
+
+    let shadow = estimate(LyapunovMethod::Shadow { separation: 1e-8 }, chaotic);
+    let variational = estimate(LyapunovMethod::Variational, chaotic);
+    assert!(shadow > 0.5, "{}", shadow);
+    assert!(
+        (shadow - variational).abs() < 0.1 * shadow,
+        "{} {}",
+        shadow,
+        variational
+    );
+
+    let regular = estimate(LyapunovMethod::Variational, regular);
+    assert!(regular < 0.1, "{}", regular);
+
+    // A separation lost to rounding puts the shadow right on the trajectory
+    let collapsed = estimate(LyapunovMethod::Shadow { separation: 1e-300 }, chaotic);
+    assert!(collapsed.is_finite(), "{}", collapsed);
+}
+
+#[test]
+fn test_lyapunov_spectrum_of_conservative_system() {
+    use crate::core::damping::Damping;
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::PendulumConfiguration;
+
+    let environment = Environment::new(9.81, 0.0, 1.0);
+    let configuration = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.5, 0.0),
+        PendulumConfiguration::new(-2.0, 0.0),
+    );
+    let estimator = LyapunovEstimator::new(
+        LyapunovMethod::Variational,
+        Duration::from_secs_f64(0.005),
+        20,
+        300,
+    );
+
+    let spectrum = |pendulum: Pendulum| {
+        estimator.spectrum(
+            &RungeKutta4,
+            &configuration,
+            &pendulum,
+            &pendulum,
+            &environment,
+            0.0,
+        )
+    };
+
+    let conservative = spectrum(Pendulum::new(1.0, 1.0));
+    let [largest, second, third, smallest] = conservative.exponents();
+    assert!(largest > 0.5, "{:?}", conservative.exponents());
+    assert!(largest >= second && second >= third && third >= smallest);
+    // Exponents come in pairs summing to zero
+    assert!(
+        conservative.sum().abs() < 0.05,
+        "{:?}",
+        conservative.exponents()
+    );
+    assert!(
+        (largest + smallest).abs() < 0.05,
+        "{:?}",
+        conservative.exponents()
+    );
+
+    let damped = spectrum(Pendulum::new(1.0, 1.0).with_damping(Damping::new(0.5, 0.0, 0.0)));
+    assert!(damped.sum() < -0.5, "{:?}", damped.exponents());
+
+    // Dependent tangents don't give NaN growths
+    let mut dependent = IDENTITY;
+    dependent[2] = [1.0, 1.0, 0.0, 0.0];
+    assert_eq!(orthonormalize(&mut dependent), None);
+    let mut overflowed = IDENTITY;
+    overflowed[1][3] = f64::INFINITY;
+    assert_eq!(orthonormalize(&mut overflowed), None);
+    let mut basis = IDENTITY;
+    assert_eq!(orthonormalize(&mut basis), Some([1.0; 4]));
+    assert_eq!(basis, IDENTITY);
+}
@@ -0,0 +1,162 @@
+use crate::core::environment::Environment;
+use crate::core::hamiltonian::{mass_matrix, CanonicalConfiguration};
+use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::f64::consts::PI;
+
+/// All states of a pendulum pair with exactly the same total energy.
+///
+/// Samples are uniform in the microcanonical (Liouville) measure: for fixed angles the momenta
+/// lie on an ellipse, and in two degrees of freedom the measure of that ellipse is proportional to
+/// `sqrt(det M)` of the mass matrix, regardless of the kinetic energy left over.
+/// So the angles are drawn by rejection with that weight, and the momenta uniformly in phase around the ellipse.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct EnergyShell {
+    pendulum_a: Pendulum,
+    pendulum_b: Pendulum,
+    environment: Environment,
+    energy: f64,
+}
+
+impl EnergyShell {
+    /// Rejection sampling of the angles gives up after this many draws per state
+    pub const MAX_ATTEMPTS: u32 = 1_000_000;
+
+    /// # Errors
+    /// If no state has the total energy `energy`, that is if it isn't above [`minimum_energy`](Self::minimum_energy)
+    pub fn new(
+        pendulum_a: Pendulum,
+        pendulum_b: Pendulum,
+        environment: Environment,
+        energy: f64,
+    ) -> Result<Self, String> {
+        let minimum_energy = EnergyShell::minimum_energy(&pendulum_a, &pendulum_b, &environment);
+
+        if energy.is_nan() || energy <= minimum_energy {
+            return Err(format!(
+                "No states with energy {}, the minimum is {}",
+                energy, minimum_energy
+            ));
+        }
+
+        Ok(EnergyShell {
+            pendulum_a,
+            pendulum_b,
+            environment,
+            energy,
+        })
+    }
+
+    /// Energy of both pendulums resting straight along gravity
+    pub fn minimum_energy(
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+    ) -> f64 {
+        -environment.gravity().abs()
+            * (pendulum_a.mass() * pendulum_a.length()
+                + pendulum_b.mass() * (pendulum_a.length() + pendulum_b.length()))
+    }
+
+    pub fn energy(&self) -> f64 {
+        self.energy
+    }
+
+    /// # Errors
+    /// If no angles were accepted within [`MAX_ATTEMPTS`](Self::MAX_ATTEMPTS) draws.
+    /// Just above the minimum energy only a tiny patch around the resting state is reachable,
+    /// so this gets likely there.
+    pub fn sample(&self, rng: &mut impl Rng) -> Result<DoublePendulumConfiguration, String> {
+        let (pendulum_a, pendulum_b) = (&self.pendulum_a, &self.pendulum_b);
+        // det M = m_b l_a^2 l_b^2 (m_a + m_b sin^2(angle_a - angle_b)), so this is its maximum
+        let max_determinant = pendulum_b.mass()
+            * (pendulum_a.length() * pendulum_b.length()).powi(2)
+            * (pendulum_a.mass() + pendulum_b.mass());
+
+        for _ in 0..Self::MAX_ATTEMPTS {
+            let angle_a = rng.gen_range(-PI..PI);
+            let angle_b = rng.gen_range(-PI..PI);
+
+            let resting = DoublePendulumConfiguration::new(
+                PendulumConfiguration::new(angle_a, 0.0),
+                PendulumConfiguration::new(angle_b, 0.0),
+            );
+            let kinetic_energy =
+                self.energy - resting.potential_energy(pendulum_a, pendulum_b, &self.environment);
+            if kinetic_energy <= 0.0 {
+                continue;
+            }
+
+            let [[m_aa, m_ab], [_, m_bb]] = mass_matrix(angle_a, angle_b, pendulum_a, pendulum_b);
+            let determinant = m_aa * m_bb - m_ab * m_ab;
+            if rng.gen::<f64>() * max_determinant.sqrt() >= determinant.sqrt() {
+                continue;
+            }
+
+            // p = sqrt(2 T) L (cos(phase), sin(phase)) with M = L L^T has p^T M^-1 p / 2 = T
+            let phase = rng.gen_range(-PI..PI);
+            let radius = f64::sqrt(2.0 * kinetic_energy);
+            let (l_aa, l_ba) = (m_aa.sqrt(), m_ab / m_aa.sqrt());
+            let l_bb = f64::sqrt(m_bb - l_ba * l_ba);
+
+            let momentum_a = radius * l_aa * phase.cos();
+            let momentum_b = radius * (l_ba * phase.cos() + l_bb * phase.sin());
+
+            return Ok(
+                CanonicalConfiguration::new(angle_a, angle_b, momentum_a, momentum_b)
+                    .to_configuration(pendulum_a, pendulum_b),
+            );
+        }
+
+        Err(format!(
+            "No state with energy {} found in {} attempts",
+            self.energy,
+            Self::MAX_ATTEMPTS
+        ))
+    }
+
+    /// `count` states, always the same ones for the same seed
+    ///
+    /// # Errors
+    /// Like [`sample`](Self::sample)
+    pub fn sample_seeded(
+        &self,
+        seed: u64,
+        count: usize,
+    ) -> Result<Vec<DoublePendulumConfiguration>, String> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        (0..count).map(|_| self.sample(&mut rng)).collect()
+    }
+}
+
+#[test]
+fn test_energy_shell_samples_have_its_energy() {
+    let pendulum_a = Pendulum::new(1.0, 2.0);
+    let pendulum_b = Pendulum::new(1.5, 1.0);
+    let environment = Environment::new(9.0, 0.3, 1.0);
+
+    let minimum_energy = EnergyShell::minimum_energy(&pendulum_a, &pendulum_b, &environment);
+    assert!(EnergyShell::new(pendulum_a, pendulum_b, environment, minimum_energy).is_err());
+    assert!(EnergyShell::new(pendulum_a, pendulum_b, environment, minimum_energy - 1.0).is_err());
+
+    for energy in [minimum_energy + 1.0, 0.0, 100.0] {
+        let shell = EnergyShell::new(pendulum_a, pendulum_b, environment, energy).unwrap();
+
+        for configuration in shell.sample_seeded(5, 1_000).unwrap() {
+            let sampled_energy = configuration.total_energy(&pendulum_a, &pendulum_b, &environment);
+            assert!(
+                (sampled_energy - energy).abs() < 1e-9,
+                "{} {}",
+                sampled_energy,
+                energy
+            );
+        }
+    }
+
+    // Barely above the minimum there is next to nothing left to hit
+    let shell =
+        EnergyShell::new(pendulum_a, pendulum_b, environment, minimum_energy + 1e-12).unwrap();
+    assert!(shell.sample_seeded(5, 1).is_err());
+}
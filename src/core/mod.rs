@@ -1,20 +1,60 @@
-use crate::core::util::{normalize_angle, normalize_angle_mut, Point, GRAVITY};
+use crate::core::chain::{link_angular_accelerations, link_generalized_forces, link_positions};
+use crate::core::clock::SimulationClock;
+use crate::core::damping::Damping;
+use crate::core::distance::{AngleProduct, DistanceMetric};
+use crate::core::ensemble::EnsembleOrigins;
+use crate::core::environment::Environment;
+use crate::core::integrator::Integrator;
+use crate::core::random::{ConfigurationDistribution, RandomSource};
+use crate::core::refinement::StepHistory;
+use crate::core::util::{normalize_angle, Point, TWO_PI};
 use rand::Rng;
 use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::f64::consts::PI;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
 use std::time::Duration;
+pub mod chain;
+pub mod clock;
+pub mod damping;
+pub mod distance;
+pub mod divergence;
+pub mod driving;
+pub mod ensemble;
+pub mod environment;
+pub mod event;
+pub mod filament;
+pub mod flip_time;
+pub mod hamiltonian;
+pub mod integrator;
+pub mod lyapunov;
+pub mod microcanonical;
+pub mod poincare;
+pub mod random;
+pub mod refinement;
 pub mod util;
 
 #[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Pendulum {
     length: f64,
     mass: f64,
+    /// Frictionless if missing, like in snapshots from before damping existed
+    #[serde(default)]
+    damping: Damping,
 }
 
 impl Pendulum {
     pub fn new(length: f64, mass: f64) -> Self {
-        Pendulum { length, mass }
+        Pendulum {
+            length,
+            mass,
+            damping: Damping::frictionless(),
+        }
+    }
+
+    pub fn with_damping(mut self, damping: Damping) -> Self {
+        self.damping = damping;
+        self
     }
 
     pub fn length(&self) -> f64 {
@@ -24,21 +64,35 @@ impl Pendulum {
     pub fn mass(&self) -> f64 {
         self.mass
     }
+
+    pub fn damping(&self) -> &Damping {
+        &self.damping
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct PendulumConfiguration {
-    /// Radians
+    /// Radians, kept in -PI..PI
+    #[serde(deserialize_with = "deserialize_angle")]
     angle: f64,
     /// Radians per \[time\]
     angular_velocity: f64,
+    /// Full counterclockwise turns the angle was wrapped by, clockwise ones count negative
+    #[serde(default)]
+    winding: i64,
+    /// Times the arm went over the top, in either direction
+    #[serde(default)]
+    flips: u64,
 }
 
 impl PendulumConfiguration {
+    /// `angle` is brought into -PI..PI right away, that doesn't count as a turn
     pub fn new(angle: f64, angular_velocity: f64) -> Self {
         PendulumConfiguration {
-            angle,
+            angle: normalize_angle(angle),
             angular_velocity,
+            winding: 0,
+            flips: 0,
         }
     }
 
@@ -49,17 +103,52 @@ impl PendulumConfiguration {
     pub fn angular_velocity(&self) -> f64 {
         self.angular_velocity
     }
+
+    pub fn winding(&self) -> i64 {
+        self.winding
+    }
+
+    pub fn flips(&self) -> u64 {
+        self.flips
+    }
+
+    /// The angle including every full turn made since the start
+    pub fn unwrapped_angle(&self) -> f64 {
+        self.angle + TWO_PI * self.winding as f64
+    }
+
+    /// Brings the angle back into -PI..PI, counting the turns that takes
+    pub(crate) fn wrap_angle(&mut self) {
+        let wrapped = normalize_angle(self.angle);
+        let turns = ((self.angle - wrapped) / TWO_PI).round() as i64;
+
+        self.angle = wrapped;
+        self.winding += turns;
+        self.flips += turns.unsigned_abs();
+    }
+}
+
+/// Like [`PendulumConfiguration::new`], an angle from outside -PI..PI is no turn
+fn deserialize_angle<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    f64::deserialize(deserializer).map(normalize_angle)
 }
 
 #[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct DoublePendulumConfiguration {
     a: PendulumConfiguration,
     b: PendulumConfiguration,
+    /// Step size \[time\] an adaptive integrator ended its last step with, it picks up from there next time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    adaptive_step: Option<f64>,
 }
 
 impl DoublePendulumConfiguration {
     pub fn new(a: PendulumConfiguration, b: PendulumConfiguration) -> Self {
-        DoublePendulumConfiguration { a, b }
+        DoublePendulumConfiguration {
+            a,
+            b,
+            adaptive_step: None,
+        }
     }
 
     pub fn a_configuration(&self) -> PendulumConfiguration {
@@ -71,22 +160,12 @@ impl DoublePendulumConfiguration {
     }
 
     pub fn random_configuration() -> Self {
-        let mut rng = rand::thread_rng();
-        let angle_a = rng.gen_range(-PI..PI);
-        let angle_b = rng.gen_range(-PI..PI);
-        let ang_vel_a = rng.gen_range(-PI..PI);
-        let ang_vel_b = rng.gen_range(-PI..PI);
+        DoublePendulumConfiguration::random_configuration_with(&mut rand::thread_rng())
+    }
 
-        DoublePendulumConfiguration {
-            a: PendulumConfiguration {
-                angle: angle_a,
-                angular_velocity: ang_vel_a,
-            },
-            b: PendulumConfiguration {
-                angle: angle_b,
-                angular_velocity: ang_vel_b,
-            },
-        }
+    /// Like [`random_configuration`](Self::random_configuration), but reproducible with a seeded `rng`
+    pub fn random_configuration_with(rng: &mut impl Rng) -> Self {
+        ConfigurationDistribution::default().sample(rng)
     }
 
     pub fn a_position(&self, pendulum_a: &Pendulum) -> Point {
@@ -100,110 +179,328 @@ impl DoublePendulumConfiguration {
     }
 
     pub fn positions(&self, pendulum_a: &Pendulum, pendulum_b: &Pendulum) -> (Point, Point) {
-        let a_position = self.a_position(pendulum_a);
+        let (links, pendulums) = ([self.a, self.b], [*pendulum_a, *pendulum_b]);
+        let mut positions = link_positions(&links, &pendulums);
+        let a_position = positions.next().expect("two links");
+        let b_position = positions.next().expect("two links");
 
-        let b_angle = self.b.angle;
-        let b_length = pendulum_b.length;
+        (a_position, b_position)
+    }
+
+    /// 0 is exactly identical, 1 is theoretical maximum distance.
+    /// The product of the normalized angle differences, see [`AngleProduct`].
+    pub fn distance(&self, other: &DoublePendulumConfiguration) -> f64 {
+        AngleProduct.distance(&[self.a, self.b], &[], &[other.a, other.b], &[])
+    }
 
-        let b_offset = Point {
-            x: b_length * b_angle.sin(),
-            y: -b_length * b_angle.cos(),
+    /// `fraction` of the way from this configuration to `other`, angles the short way around.
+    /// Winding and flip counts are those of this configuration.
+    pub fn interpolate(&self, other: &DoublePendulumConfiguration, fraction: f64) -> Self {
+        let interpolate_link = |link: PendulumConfiguration, other_link: PendulumConfiguration| {
+            PendulumConfiguration {
+                angle: normalize_angle(
+                    link.angle + normalize_angle(other_link.angle - link.angle) * fraction,
+                ),
+                angular_velocity: link.angular_velocity
+                    + (other_link.angular_velocity - link.angular_velocity) * fraction,
+                ..link
+            }
         };
 
-        (a_position, a_position + b_offset)
+        DoublePendulumConfiguration::new(
+            interpolate_link(self.a, other.a),
+            interpolate_link(self.b, other.b),
+        )
     }
 
-    /// 0 is exactly identical, 1 is theoretical maximum distance
-    pub fn distance(&self, other: &DoublePendulumConfiguration) -> f64 {
-        // Get normalized angle distances (between 0 and 1)
-        let angle_distance_a = f64::abs(normalize_angle(self.a.angle - other.a.angle));
-        let angle_distance_b = f64::abs(normalize_angle(self.b.angle - other.b.angle));
+    pub fn kinetic_energy(&self, pendulum_a: &Pendulum, pendulum_b: &Pendulum) -> f64 {
+        let ang_vel_a = self.a.angular_velocity;
+        let ang_vel_b = self.b.angular_velocity;
+        let len_a = pendulum_a.length;
+        let len_b = pendulum_b.length;
 
-        // Between 0 and 1
-        let norm_angle_distance_a = angle_distance_a / PI;
-        let norm_angle_distance_b = angle_distance_b / PI;
+        0.5 * (pendulum_a.mass + pendulum_b.mass) * len_a * len_a * ang_vel_a * ang_vel_a
+            + 0.5 * pendulum_b.mass * len_b * len_b * ang_vel_b * ang_vel_b
+            + pendulum_b.mass
+                * len_a
+                * len_b
+                * ang_vel_a
+                * ang_vel_b
+                * f64::cos(self.a.angle - self.b.angle)
+    }
 
-        assert!(
-            (0.0..=1.0).contains(&norm_angle_distance_a),
-            "{}",
-            norm_angle_distance_a
-        );
-        /*if !(0.0..=1.0).contains(&norm_angle_distance_a) {
-            eprintln!("assertion failure, not panicking today: norm_angle_distance_a: {}", norm_angle_distance_a);
-        }*/
-        assert!(
-            (0.0..=1.0).contains(&norm_angle_distance_b),
-            "{}",
-            norm_angle_distance_b
-        );
-        /*if !(0.0..=1.0).contains(&norm_angle_distance_b) {
-            eprintln!("assertion failure, not panicking today: norm_angle_distance_b: {}", norm_angle_distance_b);
-        }*/
+    /// Zero at the pivot, increasing against the direction of gravity
+    pub fn potential_energy(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+    ) -> f64 {
+        let (a_position, b_position) = self.positions(pendulum_a, pendulum_b);
+        let gravity = environment.gravity_vector();
 
-        norm_angle_distance_a * norm_angle_distance_b
+        -pendulum_a.mass * (gravity.x * a_position.x + gravity.y * a_position.y)
+            - pendulum_b.mass * (gravity.x * b_position.x + gravity.y * b_position.y)
     }
 
-    pub fn angular_accelerations(
+    pub fn total_energy(
         &self,
         pendulum_a: &Pendulum,
         pendulum_b: &Pendulum,
-    ) -> (f64, f64) {
-        let mass_a = pendulum_a.mass;
-        let mass_b = pendulum_b.mass;
-        let angle_a = self.a.angle;
-        let angle_b = self.b.angle;
+        environment: &Environment,
+    ) -> f64 {
+        self.kinetic_energy(pendulum_a, pendulum_b)
+            + self.potential_energy(pendulum_a, pendulum_b, environment)
+    }
+
+    /// Angular momentum of both masses about the pivot, counterclockwise is positive
+    pub fn angular_momentum(&self, pendulum_a: &Pendulum, pendulum_b: &Pendulum) -> f64 {
         let ang_vel_a = self.a.angular_velocity;
         let ang_vel_b = self.b.angular_velocity;
         let len_a = pendulum_a.length;
         let len_b = pendulum_b.length;
 
-        let double_mass_a = 2.0 * mass_a;
-        let angle_diff = angle_a - angle_b;
-        let angle_diff_cos = angle_diff.cos();
-        let angle_diff_sin = angle_diff.sin();
-        let double_angle_diff_sin = 2.0 * angle_diff_sin;
-        let double_angle_diff = 2.0 * angle_diff;
-        let doubled_angles_diff_cos = double_angle_diff.cos();
-        let ang_vel_a_sq = ang_vel_a * ang_vel_a;
-        let ang_vel_b_sq = ang_vel_b * ang_vel_b;
+        pendulum_a.mass * len_a * len_a * ang_vel_a
+            + pendulum_b.mass
+                * (len_a * len_a * ang_vel_a
+                    + len_b * len_b * ang_vel_b
+                    + len_a
+                        * len_b
+                        * (ang_vel_a + ang_vel_b)
+                        * f64::cos(self.a.angle - self.b.angle))
+    }
 
-        let mass_sum = mass_a + mass_b;
+    /// Generalized forces (torques on the two angles) of everything besides gravity,
+    /// i.e. damping, driving torques and the inertial forces of an accelerating pivot
+    pub fn generalized_forces(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> (f64, f64) {
+        let mut forces = [0.0; 2];
+        link_generalized_forces(
+            &[self.a, self.b],
+            &[*pendulum_a, *pendulum_b],
+            environment,
+            time,
+            &mut forces,
+        );
 
-        // Spanish wikipedia has the equations lol https://es.wikipedia.org/wiki/Doble_p%C3%A9ndulo#Ecuaciones_de_movimiento
-        let ang_acc_a = (-GRAVITY * (double_mass_a + mass_b) * angle_a.sin()
-            - mass_b * GRAVITY * f64::sin(angle_a - 2.0 * angle_b)
-            - double_angle_diff_sin
-                * mass_b
-                * (ang_vel_b_sq * len_b + ang_vel_a_sq * len_a * angle_diff_cos))
-            / (len_a * (double_mass_a + mass_b - mass_b * doubled_angles_diff_cos));
+        (forces[0], forces[1])
+    }
 
-        let ang_acc_b = double_angle_diff_sin
-            * (ang_vel_a_sq * len_a * mass_sum
-                + GRAVITY * mass_sum * angle_a.cos()
-                + ang_vel_b_sq * len_b * mass_b * angle_diff_cos)
-            / (len_b * (2.0 * mass_a + mass_b - mass_b * doubled_angles_diff_cos));
+    /// The two link case of [`link_angular_accelerations`]
+    pub fn angular_accelerations(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> (f64, f64) {
+        let mut accelerations = [0.0; 2];
+        link_angular_accelerations(
+            &[self.a, self.b],
+            &[*pendulum_a, *pendulum_b],
+            environment,
+            time,
+            &mut accelerations,
+        );
+
+        (accelerations[0], accelerations[1])
+    }
+
+    /// `[angle_a, angle_b, angular_velocity_a, angular_velocity_b]`
+    fn state(&self) -> [f64; 4] {
+        [
+            self.a.angle,
+            self.b.angle,
+            self.a.angular_velocity,
+            self.b.angular_velocity,
+        ]
+    }
+
+    /// This configuration with the angles and angular velocities of `state`, see [`state`](Self::state)
+    fn with_state(&self, state: &[f64]) -> Self {
+        DoublePendulumConfiguration {
+            a: PendulumConfiguration {
+                angle: state[0],
+                angular_velocity: state[2],
+                ..self.a
+            },
+            b: PendulumConfiguration {
+                angle: state[1],
+                angular_velocity: state[3],
+                ..self.b
+            },
+            adaptive_step: self.adaptive_step,
+        }
+    }
+
+    /// Time derivative of `[angle_a, angle_b, angular_velocity_a, angular_velocity_b]`
+    fn derivative(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> [f64; 4] {
+        let (ang_acc_a, ang_acc_b) =
+            self.angular_accelerations(pendulum_a, pendulum_b, environment, time);
+
+        [
+            self.a.angular_velocity,
+            self.b.angular_velocity,
+            ang_acc_a,
+            ang_acc_b,
+        ]
+    }
+
+    /// Jacobian of the time derivative of `[angle_a, angle_b, angular_velocity_a, angular_velocity_b]`
+    /// with respect to that state. Row `i` column `j` is the derivative of component `i` with respect to component `j`.
+    ///
+    /// This is not the exact tangent-linear model but central differences of the equations of motion
+    /// with a step of `1e-6` in every component, accurate to about `1e-6` relative where the equations are smooth.
+    /// Coulomb friction jumps at zero relative angular velocity, within `1e-6` of that the velocity columns
+    /// pick up a spike of the jump over the step instead of a derivative, and nothing meaningful exists at the jump itself.
+    pub fn jacobian(
+        &self,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+    ) -> [[f64; 4]; 4] {
+        const STEP: f64 = 1e-6;
+
+        let mut jacobian = [[0.0; 4]; 4];
+        for j in 0..4 {
+            let mut direction = [0.0; 4];
+            direction[j] = 1.0;
+
+            let forward =
+                self.offset(&direction, STEP)
+                    .derivative(pendulum_a, pendulum_b, environment, time);
+            let backward = self.offset(&direction, -STEP).derivative(
+                pendulum_a,
+                pendulum_b,
+                environment,
+                time,
+            );
+
+            for (row, (forward, backward)) in jacobian.iter_mut().zip(forward.iter().zip(&backward))
+            {
+                row[j] = (forward - backward) / (2.0 * STEP);
+            }
+        }
+
+        jacobian
+    }
+
+    /// Moves this configuration along `derivative` for `h` \[time\], without normalizing the angles
+    fn offset(&self, derivative: &[f64; 4], h: f64) -> Self {
+        DoublePendulumConfiguration {
+            a: PendulumConfiguration {
+                angle: self.a.angle + derivative[0] * h,
+                angular_velocity: self.a.angular_velocity + derivative[2] * h,
+                ..self.a
+            },
+            b: PendulumConfiguration {
+                angle: self.b.angle + derivative[1] * h,
+                angular_velocity: self.b.angular_velocity + derivative[3] * h,
+                ..self.b
+            },
+            adaptive_step: self.adaptive_step,
+        }
+    }
+
+    /// Brings both angles back into -PI..PI, counting the turns that takes
+    pub(crate) fn wrap_angles(&mut self) {
+        self.a.wrap_angle();
+        self.b.wrap_angle();
+    }
+
+    /// Steps from `time` by `duration` scaled with the environment's time scale
+    pub fn step(
+        &mut self,
+        integrator: &impl Integrator,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        environment: &Environment,
+        time: f64,
+        duration: Duration,
+    ) {
+        integrator.step(
+            self,
+            pendulum_a,
+            pendulum_b,
+            environment,
+            time,
+            environment.scale(duration),
+        );
+    }
+}
 
-        (ang_acc_a, ang_acc_b)
+/// How far the energies of a collection moved away from those of a reference collection
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EnergyDrift {
+    /// `energy - reference_energy` for every configuration
+    per_member: Vec<f64>,
+    /// Largest absolute drift
+    max: f64,
+    /// Mean absolute drift
+    mean: f64,
+}
+
+impl EnergyDrift {
+    pub fn per_member(&self) -> &Vec<f64> {
+        &self.per_member
     }
 
-    pub fn step(&mut self, pendulum_a: &Pendulum, pendulum_b: &Pendulum, duration: Duration) {
-        let (ang_acc_a, ang_acc_b) = self.angular_accelerations(pendulum_a, pendulum_b);
-        let secs = duration.as_secs_f64();
+    pub fn max(&self) -> f64 {
+        self.max
+    }
 
-        self.a.angular_velocity += ang_acc_a * secs;
-        self.b.angular_velocity += ang_acc_b * secs;
-        self.a.angle += self.a.angular_velocity * secs;
-        self.b.angle += self.b.angular_velocity * secs;
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
 
-        normalize_angle_mut(&mut self.a.angle);
-        normalize_angle_mut(&mut self.b.angle);
+    /// Indices of the configurations whose absolute drift exceeds `tolerance`
+    pub fn untrustworthy(&self, tolerance: f64) -> Vec<usize> {
+        self.per_member
+            .iter()
+            .enumerate()
+            .filter(|(_, drift)| drift.abs() > tolerance)
+            .map(|(i, _)| i)
+            .collect()
     }
 }
 
+/// Deserializing checks that there is one pendulum pair for every configuration
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct DoublePendulumCollection {
+    /// Shared by all configurations, unless they have their own in `member_pendulums`
     pendulum_a: Pendulum,
     pendulum_b: Pendulum,
+    /// If present, one pendulum pair for every configuration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    member_pendulums: Option<Vec<(Pendulum, Pendulum)>>,
+    /// Where the initial configurations came from, if they were drawn randomly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    random_source: Option<RandomSource>,
+    /// Where every configuration sits in parameter space, if the collection came from an ensemble
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origins: Option<EnsembleOrigins>,
+    /// Everything needed to replay new members up to the current time, if the collection can be refined
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    history: Option<StepHistory>,
+    /// Snapshots from before the environment was configurable use the default one
+    #[serde(default)]
+    environment: Environment,
+    /// Simulated \[time\] and steps since the start, the time is where the forcing of the environment is evaluated
+    #[serde(flatten)]
+    clock: SimulationClock,
     pendulum_configurations: Vec<DoublePendulumConfiguration>,
 }
 
@@ -216,10 +513,78 @@ impl DoublePendulumCollection {
         DoublePendulumCollection {
             pendulum_a,
             pendulum_b,
+            member_pendulums: None,
+            random_source: None,
+            origins: None,
+            history: None,
+            environment: Environment::default(),
+            clock: SimulationClock::new(),
             pendulum_configurations,
         }
     }
 
+    /// `count` configurations drawn from `random_source`. The source is saved with the collection.
+    pub fn random(
+        pendulum_a: Pendulum,
+        pendulum_b: Pendulum,
+        random_source: RandomSource,
+        count: usize,
+    ) -> Self {
+        let mut collection =
+            DoublePendulumCollection::new(pendulum_a, pendulum_b, random_source.sample(count));
+        collection.random_source = Some(random_source);
+        collection
+    }
+
+    /// A collection where every configuration has its own pendulums.
+    /// [`pendulum_a`](Self::pendulum_a) and [`pendulum_b`](Self::pendulum_b) are then those of the first member,
+    /// so there has to be at least one.
+    pub fn heterogeneous(
+        members: Vec<(Pendulum, Pendulum, DoublePendulumConfiguration)>,
+    ) -> Result<Self, String> {
+        let &(pendulum_a, pendulum_b, _) = members
+            .first()
+            .ok_or("A heterogeneous collection needs at least one member")?;
+        let (member_pendulums, pendulum_configurations) = members
+            .into_iter()
+            .map(|(pendulum_a, pendulum_b, configuration)| {
+                ((pendulum_a, pendulum_b), configuration)
+            })
+            .unzip();
+
+        Ok(DoublePendulumCollection {
+            pendulum_a,
+            pendulum_b,
+            member_pendulums: Some(member_pendulums),
+            random_source: None,
+            origins: None,
+            history: None,
+            environment: Environment::default(),
+            clock: SimulationClock::new(),
+            pendulum_configurations,
+        })
+    }
+
+    /// Recorded in the step history, if there is one
+    pub fn with_environment(mut self, environment: Environment) -> Self {
+        self.environment = environment;
+        if let Some(history) = &mut self.history {
+            history.record_environment(environment);
+        }
+        self
+    }
+
+    /// Remembers the current configurations and every step from now on,
+    /// so [`Refinement`](refinement::Refinement) can insert members later
+    pub fn with_step_history(mut self) -> Self {
+        self.history = Some(StepHistory::new(
+            self.clock,
+            self.environment,
+            &self.pendulum_configurations,
+        ));
+        self
+    }
+
     pub fn pendulum_a(&self) -> &Pendulum {
         &self.pendulum_a
     }
@@ -228,27 +593,452 @@ impl DoublePendulumCollection {
         &self.pendulum_b
     }
 
+    pub fn random_source(&self) -> Option<&RandomSource> {
+        self.random_source.as_ref()
+    }
+
+    /// Distance between each member and the next one by `metric`,
+    /// 0 is exactly identical, 1 is theoretical maximum distance
+    pub fn neighbour_distances(&self, metric: &impl DistanceMetric) -> Vec<f64> {
+        let links = |i: usize| {
+            let configuration = &self.pendulum_configurations[i];
+            let (pendulum_a, pendulum_b) = self.member_pendulums(i);
+
+            (
+                [configuration.a, configuration.b],
+                [*pendulum_a, *pendulum_b],
+            )
+        };
+
+        (1..self.pendulum_configurations.len())
+            .into_par_iter()
+            .map(|i| {
+                let (first, first_pendulums) = links(i - 1);
+                let (second, second_pendulums) = links(i);
+                metric.distance(&first, &first_pendulums, &second, &second_pendulums)
+            })
+            .collect()
+    }
+
+    /// How often each member's arms went over the top, `(a, b)`
+    pub fn flip_counts(&self) -> Vec<(u64, u64)> {
+        self.pendulum_configurations
+            .iter()
+            .map(|configuration| (configuration.a.flips, configuration.b.flips))
+            .collect()
+    }
+
+    /// How many members flipped how often, keyed by the flips of both arms together.
+    /// Only flip counts some member has are in there.
+    pub fn flip_histogram(&self) -> BTreeMap<u64, usize> {
+        let mut histogram = BTreeMap::new();
+
+        for (flips_a, flips_b) in self.flip_counts() {
+            *histogram.entry(flips_a + flips_b).or_insert(0) += 1;
+        }
+
+        histogram
+    }
+
+    pub fn origins(&self) -> Option<&EnsembleOrigins> {
+        self.origins.as_ref()
+    }
+
+    pub fn history(&self) -> Option<&StepHistory> {
+        self.history.as_ref()
+    }
+
+    pub fn is_heterogeneous(&self) -> bool {
+        self.member_pendulums.is_some()
+    }
+
+    /// The pendulums the configuration at `index` uses
+    ///
+    /// # Panics
+    /// If there is no configuration at `index`
+    pub fn member_pendulums(&self, index: usize) -> (&Pendulum, &Pendulum) {
+        pendulum_pair(
+            (&self.pendulum_a, &self.pendulum_b),
+            &self.member_pendulums,
+            index,
+        )
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn time(&self) -> f64 {
+        self.clock.time()
+    }
+
+    /// Number of integrator steps every member took so far
+    pub fn steps(&self) -> u64 {
+        self.clock.steps()
+    }
+
+    pub fn clock(&self) -> &SimulationClock {
+        &self.clock
+    }
+
     pub fn pendulum_configurations(&self) -> &Vec<DoublePendulumConfiguration> {
         &self.pendulum_configurations
     }
 
-    pub fn step_all(&mut self, step_time: Duration) {
-        let pendulum_a = &self.pendulum_a;
-        let pendulum_b = &self.pendulum_b;
+    /// Sets every member `fraction` of the way from the same member of `previous` to `next`,
+    /// for drawing in between two steps. Everything else is kept, so one collection can be reused for every frame.
+    ///
+    /// # Panics
+    /// If `previous`, `next` and this collection don't have the same number of configurations
+    pub fn interpolate_between(
+        &mut self,
+        previous: &[DoublePendulumConfiguration],
+        next: &[DoublePendulumConfiguration],
+        fraction: f64,
+    ) {
+        assert!(
+            previous.len() == self.pendulum_configurations.len()
+                && next.len() == self.pendulum_configurations.len(),
+            "collections have different sizes"
+        );
+
+        self.pendulum_configurations
+            .iter_mut()
+            .zip(previous.iter().zip(next))
+            .for_each(|(configuration, (previous, next))| {
+                *configuration = previous.interpolate(next, fraction);
+            });
+    }
+
+    pub fn total_energies(&self) -> Vec<f64> {
+        let environment = &self.environment;
+
+        self.pendulum_configurations
+            .par_iter()
+            .enumerate()
+            .map(|(i, pendulum)| {
+                let (pendulum_a, pendulum_b) = self.member_pendulums(i);
+                pendulum.total_energy(pendulum_a, pendulum_b, environment)
+            })
+            .collect()
+    }
+
+    /// Compares energies member by member with `reference`, usually a copy of this collection before stepping.
+    ///
+    /// # Panics
+    /// If the collections don't have the same number of configurations
+    pub fn energy_drift(&self, reference: &DoublePendulumCollection) -> EnergyDrift {
+        assert_eq!(
+            self.pendulum_configurations.len(),
+            reference.pendulum_configurations.len(),
+            "collections have different sizes"
+        );
+
+        let per_member: Vec<_> = self
+            .total_energies()
+            .into_iter()
+            .zip(reference.total_energies())
+            .map(|(energy, reference_energy)| energy - reference_energy)
+            .collect();
+
+        let max = per_member
+            .iter()
+            .fold(0.0, |max: f64, drift| max.max(drift.abs()));
+        let mean = if per_member.is_empty() {
+            0.0
+        } else {
+            per_member.iter().map(|drift| drift.abs()).sum::<f64>() / per_member.len() as f64
+        };
+
+        EnergyDrift {
+            per_member,
+            max,
+            mean,
+        }
+    }
+
+    pub fn step_all(&mut self, integrator: &(impl Integrator + Sync), step_time: Duration) {
+        let shared_pendulums = (&self.pendulum_a, &self.pendulum_b);
+        let member_pendulums = &self.member_pendulums;
+        let environment = &self.environment;
+        let time = self.clock.time();
 
         self.pendulum_configurations
             .par_iter_mut()
-            .for_each(|pendulum| pendulum.step(pendulum_a, pendulum_b, step_time));
+            .enumerate()
+            .for_each(|(index, pendulum)| {
+                let (pendulum_a, pendulum_b) =
+                    pendulum_pair(shared_pendulums, member_pendulums, index);
+                pendulum.step(
+                    integrator,
+                    pendulum_a,
+                    pendulum_b,
+                    environment,
+                    time,
+                    step_time,
+                )
+            });
+
+        self.clock
+            .advance(environment.scale(step_time).as_secs_f64(), 1);
+        self.record_steps(step_time, 1);
     }
 
-    pub fn step_all_n_times(&mut self, step_time: Duration, n: u32) {
-        let pendulum_a = &self.pendulum_a;
-        let pendulum_b = &self.pendulum_b;
+    pub fn step_all_n_times(
+        &mut self,
+        integrator: &(impl Integrator + Sync),
+        step_time: Duration,
+        n: u32,
+    ) {
+        let shared_pendulums = (&self.pendulum_a, &self.pendulum_b);
+        let member_pendulums = &self.member_pendulums;
+        let environment = &self.environment;
+        let time = self.clock.time();
+        let scaled_step_time = environment.scale(step_time).as_secs_f64();
 
         self.pendulum_configurations
             .par_iter_mut()
-            .for_each(|pendulum| {
-                (0..n).for_each(|_| pendulum.step(pendulum_a, pendulum_b, step_time))
+            .enumerate()
+            .for_each(|(index, pendulum)| {
+                let (pendulum_a, pendulum_b) =
+                    pendulum_pair(shared_pendulums, member_pendulums, index);
+                (0..n).for_each(|i| {
+                    pendulum.step(
+                        integrator,
+                        pendulum_a,
+                        pendulum_b,
+                        environment,
+                        time + i as f64 * scaled_step_time,
+                        step_time,
+                    )
+                })
             });
+
+        self.clock.advance(scaled_step_time, n);
+        self.record_steps(step_time, n);
+    }
+
+    fn record_steps(&mut self, step_time: Duration, n: u32) {
+        if let Some(history) = &mut self.history {
+            history.record_steps(step_time, n);
+        }
+    }
+}
+
+impl Serialize for DoublePendulumCollection {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DoublePendulumCollection::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DoublePendulumCollection {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let collection = DoublePendulumCollection::deserialize(deserializer)?;
+
+        let members = collection.pendulum_configurations.len();
+        if let Some(member_pendulums) = &collection.member_pendulums {
+            if member_pendulums.len() != members {
+                return Err(D::Error::custom(format!(
+                    "{} pendulum pairs for {} configurations",
+                    member_pendulums.len(),
+                    members
+                )));
+            }
+        }
+        if let Some(history) = &collection.history {
+            if history.initial_configurations().len() != members {
+                return Err(D::Error::custom(format!(
+                    "{} initial configurations in the step history for {} configurations",
+                    history.initial_configurations().len(),
+                    members
+                )));
+            }
+        }
+
+        Ok(collection)
+    }
+}
+
+fn pendulum_pair<'a>(
+    shared_pendulums: (&'a Pendulum, &'a Pendulum),
+    member_pendulums: &'a Option<Vec<(Pendulum, Pendulum)>>,
+    index: usize,
+) -> (&'a Pendulum, &'a Pendulum) {
+    match member_pendulums {
+        Some(member_pendulums) => {
+            let (pendulum_a, pendulum_b) = member_pendulums
+                .get(index)
+                .expect("there is one pendulum pair for every configuration");
+            (pendulum_a, pendulum_b)
+        }
+        None => shared_pendulums,
+    }
+}
+
+#[test]
+fn test_conserved_quantities() {
+    use crate::core::integrator::RungeKutta4;
+
+    let pendulum_a = Pendulum::new(1.0, 2.0);
+    let pendulum_b = Pendulum::new(1.5, 1.0);
+
+    // Hanging straight down and rotating rigidly like a single pendulum
+    let rigid = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(0.0, 2.0),
+        PendulumConfiguration::new(0.0, 2.0),
+    );
+    let inertia = 2.0 * 1.0 * 1.0 + 1.0 * 2.5 * 2.5;
+    assert!((rigid.angular_momentum(&pendulum_a, &pendulum_b) - inertia * 2.0).abs() < 1e-12);
+    assert!((rigid.kinetic_energy(&pendulum_a, &pendulum_b) - 0.5 * inertia * 4.0).abs() < 1e-12);
+    assert!(
+        (rigid.potential_energy(&pendulum_a, &pendulum_b, &Environment::default())
+            + crate::core::util::GRAVITY * (2.0 + 2.5))
+            .abs()
+            < 1e-9
+    );
+
+    let initial = DoublePendulumCollection::new(
+        pendulum_a,
+        pendulum_b,
+        vec![
+            rigid,
+            DoublePendulumConfiguration::new(
+                PendulumConfiguration::new(2.0, 0.0),
+                PendulumConfiguration::new(-1.0, 3.0),
+            ),
+        ],
+    );
+    let mut collection = initial.clone();
+    collection.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.0005), 2_000);
+
+    let drift = collection.energy_drift(&initial);
+    assert!(
+        drift.max() < 1e-6 * initial.total_energies()[1].abs(),
+        "{:?}",
+        drift
+    );
+    assert!(drift.untrustworthy(1e-3).is_empty());
+}
+
+#[test]
+fn test_heterogeneous_members_use_own_pendulums() {
+    use crate::core::integrator::RungeKutta4;
+
+    let configuration = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.0, 0.0),
+        PendulumConfiguration::new(-1.0, 3.0),
+    );
+    let pendulums = [
+        (Pendulum::new(1.0, 2.0), Pendulum::new(1.5, 1.0)),
+        (Pendulum::new(2.0, 1.0), Pendulum::new(0.5, 3.0)),
+    ];
+
+    let mut heterogeneous = DoublePendulumCollection::heterogeneous(
+        pendulums
+            .iter()
+            .map(|&(pendulum_a, pendulum_b)| (pendulum_a, pendulum_b, configuration))
+            .collect(),
+    )
+    .unwrap();
+    heterogeneous.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.001), 100);
+
+    for (i, &(pendulum_a, pendulum_b)) in pendulums.iter().enumerate() {
+        let mut homogeneous =
+            DoublePendulumCollection::new(pendulum_a, pendulum_b, vec![configuration]);
+        homogeneous.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.001), 100);
+
+        assert_eq!(
+            heterogeneous.pendulum_configurations()[i],
+            homogeneous.pendulum_configurations()[0]
+        );
     }
+
+    assert!(DoublePendulumCollection::heterogeneous(Vec::new()).is_err());
+
+    // A snapshot with a pendulum pair missing doesn't load
+    let mut value = serde_json::to_value(&heterogeneous).unwrap();
+    value["member_pendulums"].as_array_mut().unwrap().pop();
+    assert!(serde_json::from_value::<DoublePendulumCollection>(value).is_err());
+}
+
+#[test]
+fn test_winding_and_flip_counts() {
+    use crate::core::integrator::RungeKutta4;
+
+    // Without gravity a rigidly spinning double pendulum just keeps turning
+    let spinning = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(0.0, 2.0),
+        PendulumConfiguration::new(0.0, 2.0),
+    );
+    let resting = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(0.0, 0.0),
+        PendulumConfiguration::new(0.0, 0.0),
+    );
+    let mut collection = DoublePendulumCollection::new(
+        Pendulum::new(1.0, 1.0),
+        Pendulum::new(1.0, 1.0),
+        vec![spinning, resting, resting],
+    )
+    .with_environment(Environment::new(0.0, 0.0, 1.0));
+    collection.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.001), 10_000);
+
+    // 20 radians are a bit more than 3 turns, which went over the top 3 times
+    let spun = collection.pendulum_configurations()[0].a_configuration();
+    assert!((spun.unwrapped_angle() - 20.0).abs() < 1e-6, "{:?}", spun);
+    assert_eq!(spun.winding(), 3);
+    assert_eq!(collection.flip_counts(), vec![(3, 3), (0, 0), (0, 0)]);
+    assert_eq!(
+        collection.flip_histogram(),
+        BTreeMap::from([(0, 2), (6, 1)])
+    );
+
+    // Starting past the top is no flip
+    let mut past_the_top = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(4.0, 0.0),
+        PendulumConfiguration::new(-4.0, 0.0),
+    );
+    past_the_top.step(
+        &RungeKutta4,
+        &Pendulum::new(1.0, 1.0),
+        &Pendulum::new(1.0, 1.0),
+        &Environment::default(),
+        0.0,
+        Duration::from_secs_f64(0.001),
+    );
+    assert_eq!(past_the_top.a_configuration().flips(), 0);
+    assert_eq!(past_the_top.b_configuration().flips(), 0);
+
+    let json = serde_json::to_string(&collection).unwrap();
+    let loaded: DoublePendulumCollection = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.flip_counts(), collection.flip_counts());
+}
+
+#[test]
+fn test_clock_survives_snapshots() {
+    use crate::core::integrator::RungeKutta4;
+
+    let mut collection = DoublePendulumCollection::new(
+        Pendulum::new(1.0, 1.0),
+        Pendulum::new(1.0, 1.0),
+        vec![DoublePendulumConfiguration::new(
+            PendulumConfiguration::new(1.0, 0.0),
+            PendulumConfiguration::new(0.5, 0.0),
+        )],
+    );
+    collection.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.001), 300);
+    collection.step_all(&RungeKutta4, Duration::from_secs_f64(0.0007));
+    assert_eq!(collection.steps(), 301);
+
+    let json = serde_json::to_string(&collection).unwrap();
+    let resumed: DoublePendulumCollection = serde_json::from_str(&json).unwrap();
+    assert_eq!(resumed.clock(), collection.clock());
+
+    // Snapshots from before the step counter still know their time
+    let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let object = value.as_object_mut().unwrap();
+    object.remove("steps");
+    object.remove("time_compensation");
+    let old: DoublePendulumCollection = serde_json::from_value(value).unwrap();
+    assert_eq!(old.time(), collection.time());
+    assert_eq!(old.steps(), 0);
 }
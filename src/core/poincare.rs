@@ -0,0 +1,267 @@
+use crate::core::ensemble::StateVariable;
+use crate::core::event::{locate_event, Event, StateCrossing};
+use crate::core::integrator::Integrator;
+use crate::core::DoublePendulumCollection;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::time::Duration;
+
+/// Which way a [`PoincareSection`]'s surface has to be crossed to count
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum CrossingDirection {
+    /// The variable grows through the surface value
+    Ascending,
+    /// The variable shrinks through the surface value
+    Descending,
+    Both,
+}
+
+impl CrossingDirection {
+    /// Whether going from `start` to `end` crosses zero in this direction
+    pub fn crosses(&self, start: f64, end: f64) -> bool {
+        let ascending = start < 0.0 && end >= 0.0;
+        let descending = start > 0.0 && end <= 0.0;
+
+        match self {
+            CrossingDirection::Ascending => ascending,
+            CrossingDirection::Descending => descending,
+            CrossingDirection::Both => ascending || descending,
+        }
+    }
+}
+
+/// Records two state variables whenever a third one crosses a fixed value
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PoincareSection {
+    surface_variable: StateVariable,
+    /// Radians for angles, which are compared the short way around
+    surface_value: f64,
+    direction: CrossingDirection,
+    recorded: (StateVariable, StateVariable),
+}
+
+impl PoincareSection {
+    pub fn new(
+        surface_variable: StateVariable,
+        surface_value: f64,
+        direction: CrossingDirection,
+        recorded: (StateVariable, StateVariable),
+    ) -> Self {
+        PoincareSection {
+            surface_variable,
+            surface_value,
+            direction,
+            recorded,
+        }
+    }
+
+    /// The classic section: `(angle_b, angular_velocity_b)` whenever `angle_a` passes 0 with positive velocity
+    pub fn classic() -> Self {
+        PoincareSection::new(
+            StateVariable::AngleA,
+            0.0,
+            CrossingDirection::Ascending,
+            (StateVariable::AngleB, StateVariable::AngularVelocityB),
+        )
+    }
+
+    pub fn surface_variable(&self) -> StateVariable {
+        self.surface_variable
+    }
+
+    pub fn surface_value(&self) -> f64 {
+        self.surface_value
+    }
+
+    pub fn direction(&self) -> CrossingDirection {
+        self.direction
+    }
+
+    pub fn recorded(&self) -> (StateVariable, StateVariable) {
+        self.recorded
+    }
+
+    /// The surface as an [`Event`], a [`StateCrossing`] in the section's direction
+    pub fn event(&self) -> Event {
+        Event::new(
+            StateCrossing::new(self.surface_variable, self.surface_value),
+            self.direction,
+        )
+    }
+
+    /// Steps `collection` `steps` times, recording every member's crossings on the way
+    pub fn collect(
+        &self,
+        integrator: &(impl Integrator + Sync),
+        collection: &mut DoublePendulumCollection,
+        step_time: Duration,
+        steps: u32,
+    ) -> PoincarePoints {
+        let h = collection.environment().scale(step_time).as_secs_f64();
+        let event = self.event();
+        let mut points = vec![Vec::new(); collection.pendulum_configurations().len()];
+
+        for _ in 0..steps {
+            let previous = collection.pendulum_configurations().to_vec();
+            let time = collection.time();
+            collection.step_all(integrator, step_time);
+            let collection = &*collection;
+
+            points
+                .par_iter_mut()
+                .zip(
+                    previous
+                        .par_iter()
+                        .zip(collection.pendulum_configurations()),
+                )
+                .enumerate()
+                .for_each(|(i, (points, (previous, next)))| {
+                    let (pendulum_a, pendulum_b) = collection.member_pendulums(i);
+                    let environment = collection.environment();
+                    let value_at = |configuration, time| {
+                        event.function().value(
+                            configuration,
+                            pendulum_a,
+                            pendulum_b,
+                            environment,
+                            time,
+                        )
+                    };
+
+                    if !event
+                        .direction()
+                        .crosses(value_at(previous, time), value_at(next, time + h))
+                    {
+                        return;
+                    }
+
+                    let crossing = locate_event(
+                        &event,
+                        previous,
+                        next,
+                        pendulum_a,
+                        pendulum_b,
+                        environment,
+                        time,
+                        h,
+                    );
+                    if let Some((time, state)) = crossing {
+                        points.push(PoincarePoint {
+                            time,
+                            x: self.recorded.0.get(&state),
+                            y: self.recorded.1.get(&state),
+                        });
+                    }
+                });
+        }
+
+        PoincarePoints {
+            section: *self,
+            points,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PoincarePoint {
+    /// When the surface was crossed
+    time: f64,
+    /// The first recorded variable
+    x: f64,
+    /// The second recorded variable
+    y: f64,
+}
+
+impl PoincarePoint {
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn y(&self) -> f64 {
+        self.y
+    }
+}
+
+/// The crossings of every member of a collection with a [`PoincareSection`]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PoincarePoints {
+    section: PoincareSection,
+    /// One list per member, in crossing order
+    points: Vec<Vec<PoincarePoint>>,
+}
+
+impl PoincarePoints {
+    pub fn section(&self) -> &PoincareSection {
+        &self.section
+    }
+
+    pub fn points(&self) -> &[Vec<PoincarePoint>] {
+        &self.points
+    }
+
+    /// One `member,time,x,y` line per crossing
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(writer, "member,time,x,y")?;
+
+        for (member, points) in self.points.iter().enumerate() {
+            for point in points {
+                writeln!(writer, "{},{},{},{}", member, point.time, point.x, point.y)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_poincare_crossings_lie_on_surface() {
+    use crate::core::environment::Environment;
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+
+    let pendulum_a = Pendulum::new(1.0, 1.0);
+    let pendulum_b = Pendulum::new(1.0, 1.0);
+    let environment = Environment::new(9.81, 0.0, 1.0);
+    let mut collection = DoublePendulumCollection::new(
+        pendulum_a,
+        pendulum_b,
+        vec![
+            DoublePendulumConfiguration::new(
+                PendulumConfiguration::new(0.5, 0.0),
+                PendulumConfiguration::new(-0.3, 0.0),
+            ),
+            DoublePendulumConfiguration::new(
+                PendulumConfiguration::new(2.5, 0.0),
+                PendulumConfiguration::new(-2.0, 0.0),
+            ),
+        ],
+    )
+    .with_environment(environment);
+
+    // Records angle_a itself, which has to be 0 at every crossing
+    let section = PoincareSection::new(
+        StateVariable::AngleA,
+        0.0,
+        CrossingDirection::Ascending,
+        (StateVariable::AngleA, StateVariable::AngularVelocityA),
+    );
+    let points = section.collect(
+        &RungeKutta4,
+        &mut collection,
+        Duration::from_secs_f64(0.01),
+        2_000,
+    );
+
+    for member in points.points() {
+        assert!(member.len() > 3, "{}", member.len());
+        for point in member {
+            assert!(point.x().abs() < 1e-6, "{:?}", point);
+            assert!(point.y() > 0.0, "{:?}", point);
+        }
+    }
+}
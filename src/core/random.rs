@@ -0,0 +1,270 @@
+use crate::core::{DoublePendulumConfiguration, PendulumConfiguration};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::f64::consts::PI;
+
+/// How one value of a random configuration is drawn.
+/// Deserializing checks the parameters like the constructors do.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub enum Distribution {
+    /// Always the same value
+    Fixed(f64),
+    /// Uniform in `low..high`
+    Uniform {
+        low: f64,
+        high: f64,
+    },
+    Normal {
+        mean: f64,
+        standard_deviation: f64,
+    },
+}
+
+impl Distribution {
+    /// Both bounds have to be finite and `low` less than `high`
+    pub fn uniform(low: f64, high: f64) -> Result<Self, String> {
+        if !(low.is_finite() && high.is_finite() && low < high) {
+            return Err(format!(
+                "Uniform bounds {}..{} are not a finite, non-empty range",
+                low, high
+            ));
+        }
+
+        Ok(Distribution::Uniform { low, high })
+    }
+
+    /// # Panics
+    /// If `standard_deviation` is negative
+    pub fn normal(mean: f64, standard_deviation: f64) -> Self {
+        assert!(
+            standard_deviation >= 0.0,
+            "standard_deviation must not be negative"
+        );
+
+        Distribution::Normal {
+            mean,
+            standard_deviation,
+        }
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> f64 {
+        match *self {
+            Distribution::Fixed(value) => value,
+            Distribution::Uniform { low, high } => rng.gen_range(low..high),
+            Distribution::Normal {
+                mean,
+                standard_deviation,
+            } => {
+                // Box-Muller, 1 - gen() is in (0, 1] so the logarithm stays finite
+                let radius = f64::sqrt(-2.0 * f64::ln(1.0 - rng.gen::<f64>()));
+                let angle = rng.gen_range(0.0..2.0 * PI);
+
+                mean + standard_deviation * radius * angle.cos()
+            }
+        }
+    }
+}
+
+impl Serialize for Distribution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Distribution::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Distribution {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Distribution::deserialize(deserializer)? {
+            Distribution::Uniform { low, high } => {
+                Distribution::uniform(low, high).map_err(D::Error::custom)
+            }
+            Distribution::Normal {
+                standard_deviation, ..
+            } if standard_deviation < 0.0 => {
+                Err(D::Error::custom("standard_deviation must not be negative"))
+            }
+            distribution => Ok(distribution),
+        }
+    }
+}
+
+/// Draws random [`DoublePendulumConfiguration`]s, every angle and angular velocity from its own [`Distribution`]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ConfigurationDistribution {
+    angle_a: Distribution,
+    angular_velocity_a: Distribution,
+    angle_b: Distribution,
+    angular_velocity_b: Distribution,
+}
+
+impl ConfigurationDistribution {
+    pub fn new(
+        angle_a: Distribution,
+        angular_velocity_a: Distribution,
+        angle_b: Distribution,
+        angular_velocity_b: Distribution,
+    ) -> Self {
+        ConfigurationDistribution {
+            angle_a,
+            angular_velocity_a,
+            angle_b,
+            angular_velocity_b,
+        }
+    }
+
+    /// Normally distributed around `base`
+    pub fn around(
+        base: DoublePendulumConfiguration,
+        angle_standard_deviation: f64,
+        angular_velocity_standard_deviation: f64,
+    ) -> Self {
+        let a = base.a_configuration();
+        let b = base.b_configuration();
+
+        ConfigurationDistribution {
+            angle_a: Distribution::normal(a.angle(), angle_standard_deviation),
+            angular_velocity_a: Distribution::normal(
+                a.angular_velocity(),
+                angular_velocity_standard_deviation,
+            ),
+            angle_b: Distribution::normal(b.angle(), angle_standard_deviation),
+            angular_velocity_b: Distribution::normal(
+                b.angular_velocity(),
+                angular_velocity_standard_deviation,
+            ),
+        }
+    }
+
+    pub fn angle_a(&self) -> &Distribution {
+        &self.angle_a
+    }
+
+    pub fn angular_velocity_a(&self) -> &Distribution {
+        &self.angular_velocity_a
+    }
+
+    pub fn angle_b(&self) -> &Distribution {
+        &self.angle_b
+    }
+
+    pub fn angular_velocity_b(&self) -> &Distribution {
+        &self.angular_velocity_b
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> DoublePendulumConfiguration {
+        let angle_a = self.angle_a.sample(rng);
+        let angle_b = self.angle_b.sample(rng);
+        let angular_velocity_a = self.angular_velocity_a.sample(rng);
+        let angular_velocity_b = self.angular_velocity_b.sample(rng);
+
+        DoublePendulumConfiguration::new(
+            PendulumConfiguration::new(angle_a, angular_velocity_a),
+            PendulumConfiguration::new(angle_b, angular_velocity_b),
+        )
+    }
+
+    /// `count` configurations, always the same ones for the same seed
+    pub fn sample_seeded(&self, seed: u64, count: usize) -> Vec<DoublePendulumConfiguration> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        (0..count).map(|_| self.sample(&mut rng)).collect()
+    }
+}
+
+/// Every angle and angular velocity uniform in `-PI..PI`, like [`DoublePendulumConfiguration::random_configuration`]
+impl Default for ConfigurationDistribution {
+    fn default() -> Self {
+        let full_circle = Distribution::uniform(-PI, PI).expect("-PI..PI is a valid range");
+
+        ConfigurationDistribution::new(full_circle, full_circle, full_circle, full_circle)
+    }
+}
+
+/// Everything needed to draw the configurations of a collection again
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RandomSource {
+    seed: u64,
+    distribution: ConfigurationDistribution,
+}
+
+impl RandomSource {
+    pub fn new(seed: u64, distribution: ConfigurationDistribution) -> Self {
+        RandomSource { seed, distribution }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn distribution(&self) -> &ConfigurationDistribution {
+        &self.distribution
+    }
+
+    pub fn sample(&self, count: usize) -> Vec<DoublePendulumConfiguration> {
+        self.distribution.sample_seeded(self.seed, count)
+    }
+}
+
+#[test]
+fn test_seeded_sampling_is_reproducible() {
+    let base = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.0, 0.0),
+        PendulumConfiguration::new(-1.0, 3.0),
+    );
+
+    for distribution in [
+        ConfigurationDistribution::default(),
+        ConfigurationDistribution::around(base, 0.1, 0.5),
+    ] {
+        let first = distribution.sample_seeded(42, 100);
+        assert_eq!(first, distribution.sample_seeded(42, 100));
+        assert_ne!(first, distribution.sample_seeded(43, 100));
+    }
+
+    let samples = ConfigurationDistribution::around(base, 0.1, 0.0).sample_seeded(7, 10_000);
+    let mean = samples
+        .iter()
+        .map(|configuration| configuration.a_configuration().angle())
+        .sum::<f64>()
+        / samples.len() as f64;
+    assert!((mean - 2.0).abs() < 0.01, "{}", mean);
+    assert!(samples
+        .iter()
+        .all(|configuration| configuration.b_configuration().angular_velocity() == 3.0));
+}
+
+#[test]
+fn test_invalid_uniform_bounds_are_rejected() {
+    assert!(Distribution::uniform(1.0, 1.0).is_err());
+    assert!(Distribution::uniform(0.0, f64::INFINITY).is_err());
+    assert!(serde_json::from_str::<Distribution>(r#"{"Uniform":{"low":2.0,"high":1.0}}"#).is_err());
+    assert_eq!(
+        serde_json::from_str::<Distribution>(r#"{"Uniform":{"low":1.0,"high":2.0}}"#).unwrap(),
+        Distribution::uniform(1.0, 2.0).unwrap()
+    );
+}
+
+#[test]
+fn test_snapshot_rebuilds_random_collection() {
+    use crate::core::{DoublePendulumCollection, Pendulum};
+
+    let source = RandomSource::new(1234, ConfigurationDistribution::default());
+    let collection = DoublePendulumCollection::random(
+        Pendulum::new(1.0, 2.0),
+        Pendulum::new(1.5, 1.0),
+        source,
+        50,
+    );
+
+    let json = serde_json::to_string(&collection).unwrap();
+    let loaded: DoublePendulumCollection = serde_json::from_str(&json).unwrap();
+    let loaded_source = loaded.random_source().unwrap();
+
+    assert_eq!(*loaded_source, source);
+    assert_eq!(
+        loaded_source.sample(50).as_slice(),
+        collection.pendulum_configurations()
+    );
+}
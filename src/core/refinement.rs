@@ -0,0 +1,551 @@
+use crate::core::clock::SimulationClock;
+use crate::core::distance::DistanceMetric;
+use crate::core::environment::Environment;
+use crate::core::event::{step_member, Event, EventOccurrence, EventTracker};
+use crate::core::integrator::Integrator;
+use crate::core::{DoublePendulumCollection, DoublePendulumConfiguration, Pendulum};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Initial configurations and everything that happened to a collection since, see [`DoublePendulumCollection::with_step_history`]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StepHistory {
+    /// Collection clock when the history started
+    start: SimulationClock,
+    /// Collection environment when the history started
+    environment: Environment,
+    /// Configuration of every member at the start
+    initial_configurations: Vec<DoublePendulumConfiguration>,
+    /// In order
+    entries: Vec<HistoryEntry>,
+}
+
+/// Something that happened to a collection with a [`StepHistory`]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum HistoryEntry {
+    /// `repeats` stepping calls in a row with `n` steps of `step_time` each
+    Steps {
+        step_time: Duration,
+        n: u32,
+        repeats: u64,
+    },
+    /// The collection switched to this environment
+    Environment(Environment),
+}
+
+impl StepHistory {
+    pub(crate) fn new(
+        start: SimulationClock,
+        environment: Environment,
+        configurations: &[DoublePendulumConfiguration],
+    ) -> Self {
+        StepHistory {
+            start,
+            environment,
+            initial_configurations: configurations.to_vec(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn start_time(&self) -> f64 {
+        self.start.time()
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn initial_configurations(&self) -> &[DoublePendulumConfiguration] {
+        &self.initial_configurations
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Repeated stepping calls with the same arguments are kept as one entry
+    pub(crate) fn record_steps(&mut self, step_time: Duration, n: u32) {
+        if let Some(HistoryEntry::Steps {
+            step_time: last_step_time,
+            n: last_n,
+            repeats,
+        }) = self.entries.last_mut()
+        {
+            if *last_step_time == step_time && *last_n == n {
+                *repeats += 1;
+                return;
+            }
+        }
+
+        self.entries.push(HistoryEntry::Steps {
+            step_time,
+            n,
+            repeats: 1,
+        });
+    }
+
+    pub(crate) fn record_environment(&mut self, environment: Environment) {
+        self.entries.push(HistoryEntry::Environment(environment));
+    }
+
+    /// Steps `configuration` from the start exactly like the collection was stepped, watching for `events`.
+    /// Returns the events that happened and whether a terminal one stopped it early.
+    /// This takes as many steps as the collection took since the start.
+    fn replay(
+        &self,
+        integrator: &impl Integrator,
+        configuration: &mut DoublePendulumConfiguration,
+        pendulum_a: &Pendulum,
+        pendulum_b: &Pendulum,
+        events: &[Event],
+    ) -> (Vec<EventOccurrence>, bool) {
+        let mut environment = self.environment;
+        let mut clock = self.start;
+        let mut occurrences = Vec::new();
+
+        for entry in &self.entries {
+            match *entry {
+                HistoryEntry::Steps {
+                    step_time,
+                    n,
+                    repeats,
+                } => {
+                    for _ in 0..repeats {
+                        let stopped = step_member(
+                            events,
+                            integrator,
+                            configuration,
+                            &mut occurrences,
+                            pendulum_a,
+                            pendulum_b,
+                            &environment,
+                            clock.time(),
+                            step_time,
+                            n,
+                        );
+                        if stopped {
+                            return (occurrences, true);
+                        }
+                        clock.advance(environment.scale(step_time).as_secs_f64(), n);
+                    }
+                }
+                HistoryEntry::Environment(new_environment) => environment = new_environment,
+            }
+        }
+
+        (occurrences, false)
+    }
+}
+
+/// Keeps a collection a continuous filament by inserting members between neighbours that drifted apart.
+/// New members start halfway between the initial configurations of their neighbours
+/// and are replayed up to the current time, which needs a collection [with a step history](DoublePendulumCollection::with_step_history).
+///
+/// Every level looks at all neighbours once, so a single gap can be bisected once per level.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Refinement<M> {
+    metric: M,
+    /// Neighbours at least this far apart by `metric` get a member in between
+    threshold: f64,
+    /// The collection never grows beyond this many members
+    max_members: usize,
+    /// At most this many members are inserted per level, the most distant neighbours first
+    members_per_level: usize,
+    levels: u32,
+}
+
+impl<M: DistanceMetric> Refinement<M> {
+    /// One level without a per-level limit
+    pub fn new(metric: M, threshold: f64, max_members: usize) -> Self {
+        Refinement {
+            metric,
+            threshold,
+            max_members,
+            members_per_level: usize::MAX,
+            levels: 1,
+        }
+    }
+
+    pub fn with_members_per_level(mut self, members_per_level: usize) -> Self {
+        self.members_per_level = members_per_level;
+        self
+    }
+
+    pub fn with_levels(mut self, levels: u32) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    pub fn metric(&self) -> &M {
+        &self.metric
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub fn max_members(&self) -> usize {
+        self.max_members
+    }
+
+    pub fn members_per_level(&self) -> usize {
+        self.members_per_level
+    }
+
+    pub fn levels(&self) -> u32 {
+        self.levels
+    }
+
+    /// Inserts members into `collection` and returns where they ended up, in ascending order.
+    /// `integrator` has to be the one the collection was stepped with, or new members won't fit in.
+    /// A random source is dropped since it can't reproduce the collection anymore.
+    ///
+    /// Every new member is replayed from the start of the history, so this gets slower the longer the collection ran.
+    /// Member indices shift, [`DivergenceTracker`](crate::core::divergence::DivergenceTracker)s
+    /// following the collection need [`insert_members`](crate::core::divergence::DivergenceTracker::insert_members)
+    /// with the returned indices. Collections stepped by an [`EventTracker`] need [`refine_tracked`](Self::refine_tracked).
+    pub fn refine(
+        &self,
+        integrator: &(impl Integrator + Sync),
+        collection: &mut DoublePendulumCollection,
+    ) -> Result<Vec<usize>, String> {
+        self.refine_with_events(integrator, collection, &[])
+            .map(|inserted| inserted.into_iter().map(|(index, ..)| index).collect())
+    }
+
+    /// Like [`refine`](Self::refine) for a collection stepped by `tracker`.
+    /// New members are replayed with its events, stop at terminal ones and are added to it.
+    pub fn refine_tracked(
+        &self,
+        integrator: &(impl Integrator + Sync),
+        collection: &mut DoublePendulumCollection,
+        tracker: &mut EventTracker,
+    ) -> Result<Vec<usize>, String> {
+        let inserted = self.refine_with_events(integrator, collection, tracker.events())?;
+
+        Ok(inserted
+            .into_iter()
+            .map(|(index, occurrences, stopped)| {
+                tracker.insert_member(index, occurrences, stopped);
+                index
+            })
+            .collect())
+    }
+
+    /// New members with their final index, replayed events and whether a terminal one stopped them, in ascending order
+    fn refine_with_events(
+        &self,
+        integrator: &(impl Integrator + Sync),
+        collection: &mut DoublePendulumCollection,
+        events: &[Event],
+    ) -> Result<Vec<(usize, Vec<EventOccurrence>, bool)>, String> {
+        if collection.history.is_none() {
+            return Err("Collection has no step history to replay new members with".to_string());
+        }
+
+        let mut inserted: Vec<(usize, Vec<EventOccurrence>, bool)> = Vec::new();
+
+        for _ in 0..self.levels {
+            let room = self
+                .max_members
+                .saturating_sub(collection.pendulum_configurations.len());
+            let budget = usize::min(room, self.members_per_level);
+            if budget == 0 {
+                break;
+            }
+
+            let history = collection.history.as_ref().expect("checked above");
+            let initial = history.initial_configurations();
+            let distances = collection.neighbour_distances(&self.metric);
+
+            // Neighbours that started out identical can't be told apart by bisecting
+            let mut gaps: Vec<_> = distances
+                .into_iter()
+                .enumerate()
+                .filter(|&(i, distance)| {
+                    distance >= self.threshold && !same_state(&initial[i], &initial[i + 1])
+                })
+                .collect();
+            if gaps.is_empty() {
+                break;
+            }
+            gaps.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+            gaps.truncate(budget);
+            gaps.sort_by_key(|&(i, _)| i);
+
+            let new_members: Vec<_> = gaps
+                .par_iter()
+                .map(|&(i, _)| {
+                    let configuration = initial[i].interpolate(&initial[i + 1], 0.5);
+                    let (pendulum_a, pendulum_b) = midpoint_pendulums(collection, i);
+                    let mut current = configuration;
+                    let (occurrences, stopped) =
+                        history.replay(integrator, &mut current, &pendulum_a, &pendulum_b, events);
+
+                    (
+                        i,
+                        configuration,
+                        (pendulum_a, pendulum_b),
+                        current,
+                        occurrences,
+                        stopped,
+                    )
+                })
+                .collect();
+
+            // Back to front so the indices of the remaining gaps stay valid
+            for (i, configuration, pendulums, current, occurrences, stopped) in
+                new_members.into_iter().rev()
+            {
+                let index = i + 1;
+                collection.insert_member(index, configuration, pendulums, current);
+
+                // Members inserted on earlier levels or further back moved up
+                for (inserted_index, ..) in &mut inserted {
+                    if *inserted_index >= index {
+                        *inserted_index += 1;
+                    }
+                }
+                inserted.push((index, occurrences, stopped));
+            }
+        }
+
+        if !inserted.is_empty() {
+            collection.random_source = None;
+        }
+
+        inserted.sort_by_key(|&(index, ..)| index);
+        Ok(inserted)
+    }
+}
+
+impl DoublePendulumCollection {
+    fn insert_member(
+        &mut self,
+        index: usize,
+        initial_configuration: DoublePendulumConfiguration,
+        pendulums: (Pendulum, Pendulum),
+        configuration: DoublePendulumConfiguration,
+    ) {
+        if let Some(history) = &mut self.history {
+            history
+                .initial_configurations
+                .insert(index, initial_configuration);
+        }
+        if let Some(member_pendulums) = &mut self.member_pendulums {
+            member_pendulums.insert(index, pendulums);
+        }
+        if let Some(origins) = &mut self.origins {
+            origins.insert_midpoint(index - 1);
+        }
+        self.pendulum_configurations.insert(index, configuration);
+    }
+}
+
+fn same_state(first: &DoublePendulumConfiguration, second: &DoublePendulumConfiguration) -> bool {
+    [first.a, first.b]
+        .iter()
+        .zip(&[second.a, second.b])
+        .all(|(link, other_link)| {
+            link.angle == other_link.angle && link.angular_velocity == other_link.angular_velocity
+        })
+}
+
+/// Halfway between the pendulums of the members at `index` and `index + 1`, damping is taken from the first one
+fn midpoint_pendulums(collection: &DoublePendulumCollection, index: usize) -> (Pendulum, Pendulum) {
+    let (first_a, first_b) = collection.member_pendulums(index);
+    let (second_a, second_b) = collection.member_pendulums(index + 1);
+    let halfway = |pendulum: &Pendulum, other: &Pendulum| Pendulum {
+        length: (pendulum.length + other.length) / 2.0,
+        mass: (pendulum.mass + other.mass) / 2.0,
+        ..*pendulum
+    };
+
+    (halfway(first_a, second_a), halfway(first_b, second_b))
+}
+
+#[test]
+fn test_refinement_replays_new_members() {
+    use crate::core::distance::TorusEuclidean;
+    use crate::core::driving::{Driving, Signal};
+    use crate::core::ensemble::{EnsembleBuilder, ParameterRange, StateVariable};
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::PendulumConfiguration;
+
+    // Driven at first, so replaying at the wrong times or in the wrong environment would show
+    let driven = Environment::new(9.81, 0.0, 1.0).with_driving(Driving::new(
+        Signal::Zero,
+        Signal::Zero,
+        Signal::sinusoidal(2.0, 1.5, 0.0),
+        Signal::Zero,
+    ));
+    let calm = Environment::new(9.81, 0.0, 1.0);
+    let run = |collection: DoublePendulumCollection| {
+        let mut collection = collection;
+        for _ in 0..3 {
+            collection.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.001), 500);
+        }
+        let mut collection = collection.with_environment(calm);
+        collection.step_all_n_times(&RungeKutta4, Duration::from_secs_f64(0.002), 500);
+        for _ in 0..1000 {
+            collection.step_all(&RungeKutta4, Duration::from_secs_f64(0.001));
+        }
+        collection
+    };
+
+    // An ensemble, so there are origins to keep in order too
+    let base = DoublePendulumConfiguration::new(
+        PendulumConfiguration::new(2.5, 0.0),
+        PendulumConfiguration::new(-2.0, 0.0),
+    );
+    let mut collection =
+        run(
+            EnsembleBuilder::new(Pendulum::new(1.0, 1.0), Pendulum::new(1.0, 1.0), base)
+                .sweep(ParameterRange::new(StateVariable::AngleB, -2.0, -1.9), 5)
+                .build()
+                .with_environment(driven)
+                .with_step_history(),
+        );
+
+    let history = collection.history().unwrap();
+    assert_eq!(*history.environment(), driven);
+    assert_eq!(
+        history.entries(),
+        [
+            HistoryEntry::Steps {
+                step_time: Duration::from_secs_f64(0.001),
+                n: 500,
+                repeats: 3,
+            },
+            HistoryEntry::Environment(calm),
+            HistoryEntry::Steps {
+                step_time: Duration::from_secs_f64(0.002),
+                n: 500,
+                repeats: 1,
+            },
+            HistoryEntry::Steps {
+                step_time: Duration::from_secs_f64(0.001),
+                n: 1,
+                repeats: 1000,
+            },
+        ]
+    );
+
+    let refinement = Refinement::new(TorusEuclidean, 0.05, 12)
+        .with_members_per_level(3)
+        .with_levels(4);
+    let inserted = refinement.refine(&RungeKutta4, &mut collection).unwrap();
+    assert!(!inserted.is_empty());
+    assert!(inserted.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(
+        collection.pendulum_configurations().len(),
+        5 + inserted.len()
+    );
+    assert!(collection.pendulum_configurations().len() <= 12);
+
+    let origins = collection.origins().unwrap();
+    assert_eq!(origins.shape(), None);
+    assert!(origins
+        .coordinates()
+        .windows(2)
+        .all(|pair| pair[0][0] < pair[1][0]));
+
+    // Inserted members are exactly where they would be had they been there from the start
+    let initial = collection
+        .history()
+        .unwrap()
+        .initial_configurations()
+        .to_vec();
+    let fresh = run(DoublePendulumCollection::new(
+        Pendulum::new(1.0, 1.0),
+        Pendulum::new(1.0, 1.0),
+        initial,
+    )
+    .with_environment(driven));
+    assert_eq!(
+        fresh.pendulum_configurations(),
+        collection.pendulum_configurations()
+    );
+    assert_eq!(fresh.time(), collection.time());
+
+    let mut without_history = fresh;
+    assert!(refinement
+        .refine(&RungeKutta4, &mut without_history)
+        .is_err());
+}
+
+#[test]
+fn test_refinement_keeps_trackers_in_line() {
+    use crate::core::distance::TorusEuclidean;
+    use crate::core::divergence::DivergenceTracker;
+    use crate::core::ensemble::StateVariable;
+    use crate::core::event::StateCrossing;
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::poincare::CrossingDirection;
+    use crate::core::PendulumConfiguration;
+
+    // Released from rest, the first arm swings down through 0 and stops there
+    let events = || {
+        vec![Event::new(
+            StateCrossing::new(StateVariable::AngleA, 0.0),
+            CrossingDirection::Descending,
+        )
+        .terminal()]
+    };
+    let released: Vec<_> = (0..4)
+        .map(|i| {
+            DoublePendulumConfiguration::new(
+                PendulumConfiguration::new(1.0 + i as f64 * 0.5, 0.0),
+                PendulumConfiguration::new(0.0, 0.0),
+            )
+        })
+        .collect();
+    let run = |configurations: Vec<DoublePendulumConfiguration>| {
+        let mut collection = DoublePendulumCollection::new(
+            Pendulum::new(1.0, 1.0),
+            Pendulum::new(1.0, 1.0),
+            configurations,
+        )
+        .with_step_history();
+        let mut tracker = EventTracker::new(events(), &collection);
+        tracker.step_all_n_times(
+            &RungeKutta4,
+            &mut collection,
+            Duration::from_secs_f64(0.01),
+            100,
+        );
+        (collection, tracker)
+    };
+
+    let (mut collection, mut tracker) = run(released);
+    let mut divergence = DivergenceTracker::new(TorusEuclidean, 0.5, &collection);
+    divergence.record(&collection);
+    assert!((0..4).all(|i| tracker.is_stopped(i)));
+
+    let inserted = Refinement::new(TorusEuclidean, 0.01, 7)
+        .refine_tracked(&RungeKutta4, &mut collection, &mut tracker)
+        .unwrap();
+    assert!(!inserted.is_empty());
+    let pairs = collection.pendulum_configurations().len() - 1;
+    assert_eq!(pairs, 3 + inserted.len());
+    divergence.insert_members(&inserted);
+    divergence.record(&collection);
+    assert_eq!(divergence.divergence_times().len(), pairs);
+    assert!(divergence.distances()[0][inserted[0]].is_nan());
+    assert!(divergence.distances().iter().all(|row| row.len() == pairs));
+
+    // New members stopped at the event instead of swinging on, like they would have from the start
+    let initial = collection
+        .history()
+        .unwrap()
+        .initial_configurations()
+        .to_vec();
+    let (fresh, fresh_tracker) = run(initial);
+    assert!((0..=pairs).all(|i| tracker.is_stopped(i)));
+    assert_eq!(
+        fresh.pendulum_configurations(),
+        collection.pendulum_configurations()
+    );
+    assert_eq!(fresh_tracker.occurrences(), tracker.occurrences());
+}
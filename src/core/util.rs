@@ -1,6 +1,7 @@
 use std::f64::consts::PI;
 use std::ops::Add;
 
+/// Default gravitational acceleration of an [`Environment`](crate::core::environment::Environment)
 pub const GRAVITY: f64 = 100.0;
 pub const TWO_PI: f64 = 2.0 * PI;
 
@@ -1,12 +1,13 @@
-mod core;
-mod render;
-
-use crate::core::{
+use double_pendulum::core::ensemble::{EnsembleBuilder, ParameterRange, StateVariable};
+use double_pendulum::core::integrator::{Integrator, SemiImplicitEuler};
+use double_pendulum::core::{
     DoublePendulumCollection, DoublePendulumConfiguration, Pendulum, PendulumConfiguration,
 };
-use crate::render::image::ImageRenderer;
-use crate::render::sdl2::SDL2Renderer;
-use crate::render::Renderer;
+use double_pendulum::render::image::ImageRenderer;
+use double_pendulum::render::offline::OfflineRendering;
+use double_pendulum::render::realtime::FixedTimestep;
+use double_pendulum::render::sdl2::SDL2Renderer;
+use double_pendulum::render::Renderer;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::render::BlendMode;
@@ -27,44 +28,50 @@ fn main() -> Result<(), String> {
         PendulumConfiguration::new(PI, PI / 2.0),
         PendulumConfiguration::new(PI - 3.0, PI / 4.0),
     );
-    let initial_a_configuration = initial_configuration.a_configuration();
-    let initial_b_configuration = initial_configuration.b_configuration();
-
-    let pendulum_configurations: Vec<_> = (0..5_000)
-        .map(|i| {
-            DoublePendulumConfiguration::new(
-                initial_a_configuration,
-                PendulumConfiguration::new(
-                    initial_b_configuration.angle() + 0.00000001 * i as f64,
-                    initial_b_configuration.angular_velocity(),
-                ),
-            )
-        })
-        .collect();
-
-    let mut pendulums = DoublePendulumCollection::new(pend_a, pend_b, pendulum_configurations);
-
+    let initial_b_angle = initial_configuration.b_configuration().angle();
+
+    let mut pendulums = EnsembleBuilder::new(pend_a, pend_b, initial_configuration)
+        .sweep(
+            ParameterRange::new(
+                StateVariable::AngleB,
+                initial_b_angle,
+                initial_b_angle + 0.00000001 * 4_999.0,
+            ),
+            5_000,
+        )
+        .build();
+    let initial_pendulums = pendulums.clone();
+
+    // Swap in RungeKutta4 for accuracy over speed
+    let integrator = SemiImplicitEuler;
     let target_step = Duration::from_secs_f64(0.0001);
-    // Aiming for 60fps if we get realtime physics
-    let target_steps_per_render = (1.0 / 60.0 / target_step.as_secs_f64()) as u32;
+    // Aiming for 60fps, in whole microseconds so it splits into equal steps
+    let frame_interval = Duration::from_micros(16_667);
 
     if render_in_window {
-        render_to_sdl2_window(target_step, target_steps_per_render, &mut pendulums)?;
+        let timestep = FixedTimestep::new(target_step);
+        render_to_sdl2_window(&integrator, timestep, frame_interval, &mut pendulums)?;
     } else {
-        render_to_images(target_step, target_steps_per_render, &mut pendulums)?;
+        // Simulated 60fps, independent of how fast frames get rendered
+        let rendering = OfflineRendering::new(frame_interval, target_step)?;
+        render_to_images(&integrator, rendering, &mut pendulums)?;
     }
 
+    let energy_drift = pendulums.energy_drift(&initial_pendulums);
+    println!(
+        "Max/Mean energy drift: {}, {}",
+        energy_drift.max(),
+        energy_drift.mean()
+    );
+
     let json = serde_json::to_vec_pretty(&pendulums).map_err(|e| e.to_string())?;
-    std::fs::write(
-        "out/last_abort.json",
-        json,
-    )
-    .map_err(|e| e.to_string())
+    std::fs::write("out/last_abort.json", json).map_err(|e| e.to_string())
 }
 
 fn render_to_sdl2_window(
-    target_step: Duration,
-    target_steps_per_render: u32,
+    integrator: &(impl Integrator + Sync),
+    timestep: FixedTimestep,
+    frame_interval: Duration,
     pendulums: &mut DoublePendulumCollection,
 ) -> Result<(), String> {
     let sdl_context = sdl2::init()?;
@@ -87,7 +94,8 @@ fn render_to_sdl2_window(
     let renderer = SDL2Renderer::new(canvas);
     let mut event_pump = sdl_context.event_pump()?;
 
-    let before_calc = || {
+    // Up and down arrows for faster and slower playback
+    let before_calc = |timestep: &mut FixedTimestep| {
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. }
@@ -95,6 +103,14 @@ fn render_to_sdl2_window(
                     keycode: Some(Keycode::Escape),
                     ..
                 } => return ControlFlow::Break(()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Up),
+                    ..
+                } => timestep.set_time_scale(timestep.time_scale() * 2.0),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Down),
+                    ..
+                } => timestep.set_time_scale(timestep.time_scale() / 2.0),
                 _ => {}
             }
         }
@@ -105,26 +121,23 @@ fn render_to_sdl2_window(
     main_loop(
         renderer,
         before_calc,
-        target_step,
-        target_steps_per_render,
+        integrator,
+        timestep,
+        frame_interval,
         pendulums,
     )
 }
 
 fn render_to_images(
-    target_step: Duration,
-    target_steps_per_render: u32,
+    integrator: &(impl Integrator + Sync),
+    rendering: OfflineRendering,
     pendulums: &mut DoublePendulumCollection,
 ) -> Result<(), String> {
-    let renderer = ImageRenderer::new(
-        1080,
-        1080,
-        PathBuf::from("out"),
-    );
+    let mut renderer = ImageRenderer::new(1080, 1080, PathBuf::from("out"));
 
     static RUNNING: AtomicBool = AtomicBool::new(true);
 
-    let before_calc = || {
+    let before_frame = || {
         if RUNNING.load(Ordering::Relaxed) {
             ControlFlow::Continue(())
         } else {
@@ -134,57 +147,80 @@ fn render_to_images(
 
     ctrlc::set_handler(|| RUNNING.store(false, Ordering::Relaxed)).map_err(|e| e.to_string())?;
 
-    main_loop(
-        renderer,
-        before_calc,
-        target_step,
-        target_steps_per_render,
-        pendulums,
-    )
+    let start = Instant::now();
+    let frames = rendering.run(&mut renderer, integrator, pendulums, before_frame)?;
+    println!(
+        "Rendered {} frames, {}s simulated time in {}s",
+        frames,
+        pendulums.time(),
+        start.elapsed().as_secs_f64()
+    );
+
+    Ok(())
 }
 
 fn main_loop(
     mut renderer: impl Renderer,
-    mut before_calc: impl FnMut() -> ControlFlow<(), ()>,
-    target_step: Duration,
-    target_steps_per_render: u32,
+    mut before_calc: impl FnMut(&mut FixedTimestep) -> ControlFlow<(), ()>,
+    integrator: &(impl Integrator + Sync),
+    mut timestep: FixedTimestep,
+    frame_interval: Duration,
     pendulums: &mut DoublePendulumCollection,
 ) -> Result<(), String> {
     let mut cumulative_calc_time = Duration::ZERO;
 
-    let mut step_time;
-    let mut last_step = Instant::now();
+    let mut last_frame = Instant::now();
+    // The state before the last step, frames are drawn in between this and the current state
+    let mut previous = pendulums.pendulum_configurations().to_vec();
+    // Drawn every frame, only its configurations change
+    let mut interpolated = pendulums.clone();
 
     let mut render_iterations = 0u32;
 
     'out: loop {
         let start_calc = Instant::now();
 
-        if matches!(before_calc(), ControlFlow::Break(_)) {
+        if matches!(before_calc(&mut timestep), ControlFlow::Break(_)) {
             break 'out;
         }
 
-        step_time = Duration::min(last_step.elapsed(), target_step); // Step at most step time!!
-        last_step = Instant::now();
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
 
-        pendulums.step_all_n_times(step_time, target_steps_per_render);
+        let frame = timestep.advance(elapsed);
+        if frame.steps > 0 {
+            pendulums.step_all_n_times(integrator, timestep.step(), frame.steps - 1);
+            previous.copy_from_slice(pendulums.pendulum_configurations());
+            pendulums.step_all(integrator, timestep.step());
+        }
 
-        renderer.render_frame(pendulums)?;
+        interpolated.interpolate_between(
+            &previous,
+            pendulums.pendulum_configurations(),
+            timestep.alpha(),
+        );
+        renderer.render_frame(&interpolated)?;
 
         let calc_time = start_calc.elapsed();
         cumulative_calc_time += calc_time;
 
-        let total_iterations = render_iterations * target_steps_per_render;
-        let to_sleep = (target_step * target_steps_per_render).saturating_sub(calc_time);
+        let to_sleep = frame_interval.saturating_sub(calc_time);
         println!(
-            "step: {}s, slep: {}s, calc: {}s, render iteration: {}, total iteration: {}, total simulated time: {}s",
-            step_time.as_secs_f64(),
+            "steps: {}, slep: {}s, calc: {}s, time scale: {}, render iteration: {}, total iteration: {}, total simulated time: {}s",
+            frame.steps,
             to_sleep.as_secs_f64(),
             calc_time.as_secs_f64(),
+            timestep.time_scale(),
             render_iterations,
-            total_iterations,
-            (step_time * total_iterations).as_secs_f64(),
+            pendulums.steps(),
+            pendulums.time(),
         );
+        if frame.dropped > Duration::ZERO {
+            println!(
+                "Can't keep up, skipped {}s of simulated time",
+                frame.dropped.as_secs_f64()
+            );
+        }
         thread::sleep(to_sleep);
 
         render_iterations += 1;
@@ -0,0 +1,52 @@
+use crate::core::distance::DistanceMetric;
+use crate::core::divergence::DivergenceTracker;
+use crate::core::util::hsva_to_rgba;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// One column per pair of neighbours, one row per recording with time going down.
+/// Distances go from blue for identical to red for the maximum, pairs at or past the threshold are white.
+pub fn render_divergence_heat_map<M: DistanceMetric>(tracker: &DivergenceTracker<M>) -> RgbaImage {
+    let distances = tracker.distances();
+    let width = distances.first().map_or(0, Vec::len);
+
+    ImageBuffer::from_fn(width as u32, distances.len() as u32, |x, y| {
+        let distance = distances[y as usize][x as usize];
+
+        if distance >= tracker.threshold() {
+            Rgba([255, 255, 255, 255])
+        } else {
+            let h = 240.0 * (1.0 - distance.clamp(0.0, 1.0));
+            let (r, g, b, a) = hsva_to_rgba(h, 1.0, 1.0, 1.0);
+            Rgba([r, g, b, a])
+        }
+    })
+}
+
+/// Saves the heat map as `divergence.png`, the profile as `divergence_profile.csv`
+/// and the divergence times as `divergence_times.csv` into `directory`
+pub fn save_divergence<M: DistanceMetric>(
+    tracker: &DivergenceTracker<M>,
+    directory: &Path,
+) -> Result<(), String> {
+    render_divergence_heat_map(tracker)
+        .save(directory.join("divergence.png"))
+        .map_err(|e| e.to_string())?;
+
+    let profile =
+        File::create(directory.join("divergence_profile.csv")).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(profile);
+    tracker
+        .write_profile_csv(&mut writer)
+        .map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())?;
+
+    let times = File::create(directory.join("divergence_times.csv")).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(times);
+    tracker
+        .write_divergence_times_csv(&mut writer)
+        .map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())
+}
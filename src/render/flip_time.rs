@@ -0,0 +1,63 @@
+use crate::core::flip_time::FlipTimeMap;
+use crate::core::util::hsva_to_rgba;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// How flip times are mapped onto the colour wheel, from red for immediate flips
+/// to violet for flips at the maximum time. Members that never flip are black.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ColourScale {
+    Linear,
+    /// Spreads out the many early flips, like the classic renders
+    Logarithmic,
+}
+
+impl ColourScale {
+    /// Position of `flip_time` on the scale, between 0 and 1
+    pub fn fraction(&self, flip_time: f64, max_time: f64) -> f64 {
+        let fraction = match self {
+            ColourScale::Linear => flip_time / max_time,
+            ColourScale::Logarithmic => f64::ln_1p(flip_time) / f64::ln_1p(max_time),
+        };
+
+        fraction.clamp(0.0, 1.0)
+    }
+}
+
+/// One pixel per member, `angle_a` growing to the right and `angle_b` growing upwards
+pub fn render_flip_time_map(map: &FlipTimeMap, colour_scale: ColourScale) -> RgbaImage {
+    let settings = map.settings();
+    let (width, height) = (settings.width(), settings.height());
+
+    ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+        let flip_time = map.flip_time(x as usize, height - 1 - y as usize);
+
+        match flip_time {
+            Some(flip_time) => {
+                let h = 300.0 * colour_scale.fraction(flip_time, settings.max_time());
+                let (r, g, b, a) = hsva_to_rgba(h, 1.0, 1.0, 1.0);
+                Rgba([r, g, b, a])
+            }
+            None => Rgba([0, 0, 0, 255]),
+        }
+    })
+}
+
+/// Saves the map as `flip_times.png` and the raw data as `flip_times.csv` into `directory`
+pub fn save_flip_time_map(
+    map: &FlipTimeMap,
+    colour_scale: ColourScale,
+    directory: &Path,
+) -> Result<(), String> {
+    render_flip_time_map(map, colour_scale)
+        .save(directory.join("flip_times.png"))
+        .map_err(|e| e.to_string())?;
+
+    let csv = File::create(directory.join("flip_times.csv")).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(csv);
+    map.write_csv(&mut writer).map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())
+}
@@ -1,6 +1,6 @@
+use crate::core::distance::{AngleProduct, DistanceMetric};
 use crate::core::util::{hsva_to_rgba, Point};
-use crate::render::Renderer;
-use crate::{DoublePendulumCollection, DoublePendulumConfiguration};
+use crate::render::{Renderable, Renderer};
 use image::{ImageBuffer, Rgba};
 use imageproc::drawing;
 use imageproc::drawing::{Blend, Canvas};
@@ -8,12 +8,17 @@ use imageproc::point::Point as ImageprocPoint;
 use itertools::Itertools;
 use std::f32;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
 
-pub struct ImageRenderer {
+/// Fills the area between neighbouring members, more opaque the closer they are by `M`
+pub struct ImageRenderer<M = AngleProduct> {
     width: u32,
     height: u32,
     count: usize,
     base_path: PathBuf,
+    distance_metric: M,
+    /// Number of frames still being saved in the background
+    pending_saves: Arc<(Mutex<usize>, Condvar)>,
 }
 
 impl ImageRenderer {
@@ -23,15 +28,34 @@ impl ImageRenderer {
             height,
             count: 0,
             base_path,
+            distance_metric: AngleProduct,
+            pending_saves: Arc::default(),
         }
     }
 }
 
-impl Renderer for ImageRenderer {
-    fn render_frame(&mut self, pendulums: &DoublePendulumCollection) -> Result<(), String> {
-        let configurations_len_f64 = pendulums.pendulum_configurations().len() as f64;
-        let pendulum_a = pendulums.pendulum_a();
-        let pendulum_b = pendulums.pendulum_b();
+impl<M: DistanceMetric> ImageRenderer<M> {
+    pub fn with_distance_metric<N: DistanceMetric>(self, distance_metric: N) -> ImageRenderer<N> {
+        ImageRenderer {
+            width: self.width,
+            height: self.height,
+            count: self.count,
+            base_path: self.base_path,
+            distance_metric,
+            pending_saves: self.pending_saves,
+        }
+    }
+
+    pub fn distance_metric(&self) -> &M {
+        &self.distance_metric
+    }
+}
+
+impl<M: DistanceMetric> Renderer for ImageRenderer<M> {
+    fn render_frame(&mut self, pendulums: &impl Renderable) -> Result<(), String> {
+        let member_positions = pendulums.member_positions();
+        let neighbour_distances = pendulums.neighbour_distances(&self.distance_metric);
+        let configurations_len_f64 = member_positions.len() as f64;
 
         let mut buffer = Blend(ImageBuffer::from_pixel(
             self.width,
@@ -45,10 +69,11 @@ impl Renderer for ImageRenderer {
         let midpoint = (rel_x_max, rel_y_max);
         let midpoint_i32 = (rel_x_max as i32, rel_y_max as i32);
         let midpoint_point = ImageprocPoint::new(midpoint_i32.0, midpoint_i32.1);
-        let max_extension = pendulum_a.length() + pendulum_b.length();
-        let conversion_constant = (minimum_rel_max as f64 / max_extension) * 0.95;
+        let member_extensions = pendulums.member_extensions();
 
-        let convert_point = |point: Point| {
+        // Every member gets scaled so that it would just reach the edge when fully extended
+        let convert_point = |point: Point, extension: f64| {
+            let conversion_constant = (minimum_rel_max as f64 / extension) * 0.95;
             (
                 (conversion_constant * point.x) as f32 + midpoint.0,
                 (conversion_constant * -point.y) as f32 + midpoint.1,
@@ -57,68 +82,49 @@ impl Renderer for ImageRenderer {
 
         let blue = Rgba([0, 0, 255, 255]);
 
-        struct PendulumRenderInfo<'a> {
-            pendulum: &'a DoublePendulumConfiguration,
-            a: (f32, f32),
-            b: (f32, f32),
-            a_point: ImageprocPoint<i32>,
-            b_point: ImageprocPoint<i32>,
+        struct PendulumRenderInfo {
+            /// Every bob, starting closest to the pivot
+            points: Vec<ImageprocPoint<i32>>,
             h: f64,
         }
 
-        let render_infos: Vec<_> = pendulums
-            .pendulum_configurations()
-            .iter()
+        let render_infos: Vec<_> = member_positions
+            .into_iter()
+            .zip(member_extensions)
             .enumerate()
-            .map(|(i, pendulum)| {
-                let (a_position, b_position) = pendulum.positions(pendulum_a, pendulum_b);
-                let (new_a, new_b) = (convert_point(a_position), convert_point(b_position));
-                let (new_a_point, new_b_point) = (
-                    ImageprocPoint::new(new_a.0 as i32, new_a.1 as i32),
-                    ImageprocPoint::new(new_b.0 as i32, new_b.1 as i32),
-                );
+            .map(|(i, (positions, extension))| {
+                let points = positions
+                    .into_iter()
+                    .map(|position| {
+                        let new = convert_point(position, extension);
+                        ImageprocPoint::new(new.0 as i32, new.1 as i32)
+                    })
+                    .collect();
 
                 let curr_h = (360 * i) as f64 / configurations_len_f64;
 
-                PendulumRenderInfo {
-                    pendulum,
-                    a: new_a,
-                    b: new_b,
-                    a_point: new_a_point,
-                    b_point: new_b_point,
-                    h: curr_h,
-                }
+                PendulumRenderInfo { points, h: curr_h }
             })
             .collect();
 
-        for (info_1, info_2) in render_infos.iter().tuple_windows() {
-            let (r, g, b, a) = hsva_to_rgba(info_1.h, 1.0, 1.0, 0.01);
-            let color = Rgba([r, g, b, a]);
-
-            let new_a = info_1.a;
-            let new_b = info_1.b;
-
+        for ((info_1, info_2), distance) in
+            render_infos.iter().tuple_windows().zip(neighbour_distances)
+        {
             //drawing::draw_line_segment_mut(&mut buffer, midpoint, new_a, color);
             //drawing::draw_line_segment_mut(&mut buffer, new_a, new_b, color);
 
-            let color_weight =
-                1.0 - DoublePendulumConfiguration::distance(info_1.pendulum, info_2.pendulum);
+            let color_weight = 1.0 - distance;
             let (r, g, b, a) = hsva_to_rgba(info_1.h, 1.0, 1.0, 0.05 * color_weight);
             let color_weighed = Rgba([r, g, b, a]);
 
+            // Out along the first pendulum and back along the second one
+            let polygon: Vec<_> = std::iter::once(midpoint_point)
+                .chain(info_1.points.iter().copied())
+                .chain(info_2.points.iter().rev().copied())
+                .collect();
+
             // TODO: line between blue pixels (also depends on distance)
-            drawing::draw_polygon_mut(
-                &mut buffer,
-                [
-                    midpoint_point,
-                    info_1.a_point,
-                    info_1.b_point,
-                    info_2.b_point,
-                    info_2.a_point,
-                ]
-                .as_ref(),
-                color_weighed,
-            );
+            drawing::draw_polygon_mut(&mut buffer, &polygon, color_weighed);
             //buffer.draw_pixel(new_a.0 as u32, new_a.1 as u32, blue);
             //buffer.draw_pixel(new_b.0 as u32, new_b.1 as u32, blue);
         }
@@ -151,6 +157,8 @@ impl Renderer for ImageRenderer {
 
         let count = self.count;
         let base_path = self.base_path.clone();
+        let pending_saves = Arc::clone(&self.pending_saves);
+        *pending_saves.0.lock().expect("not poisoned") += 1;
 
         rayon::spawn_fifo(move || {
             buffer
@@ -161,10 +169,26 @@ impl Renderer for ImageRenderer {
                     eprintln!("panic: {}", e);
                     std::process::exit(-1);
                 });
+
+            let (pending, all_saved) = &*pending_saves;
+            *pending.lock().expect("not poisoned") -= 1;
+            all_saved.notify_all();
         });
 
         self.count += 1;
 
         Ok(())
     }
+
+    /// Waits until every frame is saved
+    fn finish(&mut self) -> Result<(), String> {
+        let (pending, all_saved) = &*self.pending_saves;
+        let _pending = all_saved
+            .wait_while(pending.lock().map_err(|e| e.to_string())?, |pending| {
+                *pending > 0
+            })
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
 }
@@ -1,8 +1,94 @@
-use crate::DoublePendulumCollection;
+use crate::core::chain::ChainPendulumCollection;
+use crate::core::distance::DistanceMetric;
+use crate::core::util::Point;
+use crate::core::DoublePendulumCollection;
 
+pub mod divergence;
+pub mod flip_time;
 pub mod image;
+pub mod offline;
+pub mod poincare;
+pub mod realtime;
 pub mod sdl2;
 
 pub trait Renderer {
-    fn render_frame(&mut self, pendulums: &DoublePendulumCollection) -> Result<(), String>;
+    fn render_frame(&mut self, pendulums: &impl Renderable) -> Result<(), String>;
+
+    /// Completes work still running for earlier frames, like saving them
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// A collection of pendulums with any number of links that can be drawn by a [`Renderer`]
+pub trait Renderable {
+    /// Bob positions of every member, starting with the bob closest to the pivot
+    fn member_positions(&self) -> Vec<Vec<Point>>;
+
+    /// Length of every fully extended member, used to scale each of them onto the screen
+    fn member_extensions(&self) -> Vec<f64>;
+
+    /// Distance between each member and the next one by `metric`,
+    /// 0 is exactly identical, 1 is theoretical maximum distance
+    fn neighbour_distances(&self, metric: &impl DistanceMetric) -> Vec<f64>;
+}
+
+impl Renderable for DoublePendulumCollection {
+    fn member_positions(&self) -> Vec<Vec<Point>> {
+        self.pendulum_configurations()
+            .iter()
+            .enumerate()
+            .map(|(i, pendulum)| {
+                let (pendulum_a, pendulum_b) = self.member_pendulums(i);
+                let (a_position, b_position) = pendulum.positions(pendulum_a, pendulum_b);
+                vec![a_position, b_position]
+            })
+            .collect()
+    }
+
+    fn member_extensions(&self) -> Vec<f64> {
+        (0..self.pendulum_configurations().len())
+            .map(|i| {
+                let (pendulum_a, pendulum_b) = self.member_pendulums(i);
+                pendulum_a.length() + pendulum_b.length()
+            })
+            .collect()
+    }
+
+    fn neighbour_distances(&self, metric: &impl DistanceMetric) -> Vec<f64> {
+        DoublePendulumCollection::neighbour_distances(self, metric)
+    }
+}
+
+impl Renderable for ChainPendulumCollection {
+    fn member_positions(&self) -> Vec<Vec<Point>> {
+        self.pendulum_configurations()
+            .iter()
+            .map(|pendulum| pendulum.positions(self.pendulums()))
+            .collect()
+    }
+
+    fn member_extensions(&self) -> Vec<f64> {
+        let extension = self
+            .pendulums()
+            .iter()
+            .map(|pendulum| pendulum.length())
+            .sum();
+
+        vec![extension; self.pendulum_configurations().len()]
+    }
+
+    fn neighbour_distances(&self, metric: &impl DistanceMetric) -> Vec<f64> {
+        self.pendulum_configurations()
+            .windows(2)
+            .map(|pair| {
+                metric.distance(
+                    pair[0].links(),
+                    self.pendulums(),
+                    pair[1].links(),
+                    self.pendulums(),
+                )
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,203 @@
+use crate::core::integrator::Integrator;
+use crate::core::DoublePendulumCollection;
+use crate::render::Renderer;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+/// Renders frames at a fixed interval of simulated time with a fixed integration step,
+/// as fast as the machine allows. Nothing depends on the wall clock, so the same scenario always gives the same frames.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct OfflineRendering {
+    step: Duration,
+    steps_per_frame: u32,
+    /// Stop after this many frames, if set
+    frames: Option<u32>,
+}
+
+impl OfflineRendering {
+    /// Splits `frame_interval` into equal steps of at most `max_step`, so frames are exactly `frame_interval` apart.
+    /// The step is shrunk as little as possible, and to no less than half of `max_step`.
+    /// Intervals in round numbers, like whole microseconds, usually split that way.
+    ///
+    /// # Errors
+    /// If either duration is zero or no such step exists.
+    pub fn new(frame_interval: Duration, max_step: Duration) -> Result<Self, String> {
+        let interval = frame_interval.as_nanos();
+        if interval == 0 || max_step.is_zero() {
+            return Err("Frame interval and step must not be zero".to_string());
+        }
+
+        let max_step_nanos = max_step.as_nanos();
+        let minimum_steps = (interval + max_step_nanos - 1) / max_step_nanos;
+        let steps_per_frame = (minimum_steps..=2 * minimum_steps)
+            .find(|steps| interval % steps == 0)
+            .ok_or_else(|| {
+                format!(
+                    "A frame interval of {:?} doesn't split into equal steps between {:?} and half of it",
+                    frame_interval, max_step
+                )
+            })?;
+        let steps_per_frame = u32::try_from(steps_per_frame)
+            .map_err(|_| format!("{} steps per frame are too many", steps_per_frame))?;
+
+        Ok(OfflineRendering {
+            step: frame_interval / steps_per_frame,
+            steps_per_frame,
+            frames: None,
+        })
+    }
+
+    pub fn with_frames(mut self, frames: u32) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    pub fn steps_per_frame(&self) -> u32 {
+        self.steps_per_frame
+    }
+
+    /// Simulated time between frames, before the environment's time scale
+    pub fn frame_interval(&self) -> Duration {
+        self.step * self.steps_per_frame
+    }
+
+    pub fn frames(&self) -> Option<u32> {
+        self.frames
+    }
+
+    /// Renders the current state of `pendulums` as the first frame, then steps and renders until
+    /// the frame limit is reached or `before_frame` breaks. Returns the number of rendered frames.
+    pub fn run(
+        &self,
+        renderer: &mut impl Renderer,
+        integrator: &(impl Integrator + Sync),
+        pendulums: &mut DoublePendulumCollection,
+        mut before_frame: impl FnMut() -> ControlFlow<(), ()>,
+    ) -> Result<u32, String> {
+        let mut rendered = 0;
+
+        while self.frames.map_or(true, |frames| rendered < frames) {
+            if before_frame().is_break() {
+                break;
+            }
+
+            if rendered > 0 {
+                pendulums.step_all_n_times(integrator, self.step, self.steps_per_frame);
+            }
+            renderer.render_frame(pendulums)?;
+            rendered += 1;
+        }
+
+        renderer.finish()?;
+
+        Ok(rendered)
+    }
+}
+
+#[test]
+fn test_offline_rendering_is_reproducible() {
+    use crate::core::integrator::RungeKutta4;
+    use crate::core::util::Point;
+    use crate::core::{DoublePendulumConfiguration, Pendulum, PendulumConfiguration};
+    use crate::render::image::ImageRenderer;
+    use crate::render::Renderable;
+
+    #[derive(Default)]
+    struct Recorder {
+        frames: Vec<Vec<Vec<Point>>>,
+    }
+
+    impl Renderer for Recorder {
+        fn render_frame(&mut self, pendulums: &impl Renderable) -> Result<(), String> {
+            self.frames.push(pendulums.member_positions());
+            Ok(())
+        }
+    }
+
+    // 60fps to the microsecond, 17 steps of at most 1ms would leave a remainder
+    let frame_interval = Duration::from_micros(16_667);
+    let rendering = OfflineRendering::new(frame_interval, Duration::from_millis(1))
+        .unwrap()
+        .with_frames(30);
+    assert_eq!(rendering.steps_per_frame(), 20);
+    assert_eq!(
+        rendering.step() * rendering.steps_per_frame(),
+        frame_interval
+    );
+    assert_eq!(rendering.frame_interval(), frame_interval);
+    assert!(rendering.step() <= Duration::from_millis(1));
+    // A prime number of nanoseconds can't be split at all
+    assert!(
+        OfflineRendering::new(Duration::from_nanos(1_000_003), Duration::from_micros(1)).is_err()
+    );
+
+    let run = || {
+        let mut pendulums = DoublePendulumCollection::new(
+            Pendulum::new(1.0, 1.0),
+            Pendulum::new(1.0, 1.0),
+            (0..50)
+                .map(|i| {
+                    DoublePendulumConfiguration::new(
+                        PendulumConfiguration::new(2.0, 0.0),
+                        PendulumConfiguration::new(2.0 + i as f64 * 1e-3, 0.0),
+                    )
+                })
+                .collect(),
+        );
+        let mut recorder = Recorder::default();
+        let frames = rendering
+            .run(&mut recorder, &RungeKutta4, &mut pendulums, || {
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+        assert_eq!(frames, 30);
+        assert_eq!(pendulums.steps(), 29 * 20);
+        recorder.frames
+    };
+
+    let first = run();
+    assert_eq!(first.len(), 30);
+    assert_eq!(first, run());
+
+    // Same for the saved images, byte for byte
+    let render_images = |name: &str| {
+        let directory = std::env::temp_dir().join(format!(
+            "double_pendulum_offline_{}_{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let mut pendulums = DoublePendulumCollection::new(
+            Pendulum::new(1.0, 1.0),
+            Pendulum::new(1.0, 1.0),
+            (0..20)
+                .map(|i| {
+                    DoublePendulumConfiguration::new(
+                        PendulumConfiguration::new(2.0, 0.0),
+                        PendulumConfiguration::new(2.0 + i as f64 * 1e-2, 0.0),
+                    )
+                })
+                .collect(),
+        );
+        let mut renderer = ImageRenderer::new(64, 64, directory.clone());
+        rendering
+            .with_frames(3)
+            .run(&mut renderer, &RungeKutta4, &mut pendulums, || {
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+        let images: Vec<_> = (0..3)
+            .map(|frame| std::fs::read(directory.join(format!("render_{:05}.png", frame))).unwrap())
+            .collect();
+        std::fs::remove_dir_all(directory).unwrap();
+        images
+    };
+    assert_eq!(render_images("first"), render_images("second"));
+}
@@ -0,0 +1,82 @@
+use crate::core::ensemble::StateVariable;
+use crate::core::poincare::PoincarePoints;
+use crate::core::util::hsva_to_rgba;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use imageproc::drawing::{Blend, Canvas};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Scatter plot of the crossings, every member in its own colour.
+/// Angles span the full circle, other variables the range of the recorded values.
+/// Both dimensions have to be at least one pixel.
+pub fn render_poincare_points(
+    points: &PoincarePoints,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage, String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Can't render a {}x{} image", width, height));
+    }
+
+    let (x_variable, y_variable) = points.section().recorded();
+    let all_points = || points.points().iter().flatten();
+
+    let x_range = axis_range(x_variable, all_points().map(|point| point.x()));
+    let y_range = axis_range(y_variable, all_points().map(|point| point.y()));
+
+    let mut buffer = Blend(ImageBuffer::from_pixel(width, height, Rgba([0, 0, 0, 255])));
+
+    let members_len_f64 = points.points().len() as f64;
+    for (i, member) in points.points().iter().enumerate() {
+        let h = (360 * i) as f64 / members_len_f64;
+        let (r, g, b, a) = hsva_to_rgba(h, 1.0, 1.0, 0.5);
+        let color = Rgba([r, g, b, a]);
+
+        for point in member {
+            let x = (point.x() - x_range.0) / (x_range.1 - x_range.0) * (width - 1) as f64;
+            let y = (y_range.1 - point.y()) / (y_range.1 - y_range.0) * (height - 1) as f64;
+
+            buffer.draw_pixel(x.round() as u32, y.round() as u32, color);
+        }
+    }
+
+    Ok(buffer.0)
+}
+
+/// Saves the crossings as `poincare.png` and `poincare.csv` into `directory`
+pub fn save_poincare_points(
+    points: &PoincarePoints,
+    width: u32,
+    height: u32,
+    directory: &Path,
+) -> Result<(), String> {
+    render_poincare_points(points, width, height)?
+        .save(directory.join("poincare.png"))
+        .map_err(|e| e.to_string())?;
+
+    let csv = File::create(directory.join("poincare.csv")).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(csv);
+    points.write_csv(&mut writer).map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())
+}
+
+fn axis_range(variable: StateVariable, values: impl Iterator<Item = f64>) -> (f64, f64) {
+    if variable.is_angle() {
+        return (-PI, PI);
+    }
+
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+        (f64::min(min, value), f64::max(max, value))
+    });
+
+    if min < max {
+        (min, max)
+    } else if min.is_finite() {
+        // All the same value, center it
+        (min - 1.0, min + 1.0)
+    } else {
+        (-1.0, 1.0)
+    }
+}
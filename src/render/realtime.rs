@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+/// Turns wall-clock time into whole physics steps of a fixed size.
+/// Time that doesn't make up a whole step is carried over to the next frame,
+/// and [`alpha`](Self::alpha) tells how far rendering should go from the last physics state towards the next one.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FixedTimestep {
+    step: Duration,
+    /// Simulated time per wall-clock time, below 1 is slow motion and above 1 fast forward.
+    /// This comes on top of the environment's time scale.
+    time_scale: f64,
+    /// If a frame would need more steps, the rest of the time is dropped.
+    /// This keeps frames from taking longer and longer when the physics can't keep up.
+    max_steps_per_frame: u32,
+    /// Scaled time not yet stepped
+    accumulator: Duration,
+}
+
+/// What to do for one frame, see [`FixedTimestep::advance`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FrameSteps {
+    pub steps: u32,
+    /// Scaled time that was skipped because of the step limit
+    pub dropped: Duration,
+}
+
+impl FixedTimestep {
+    /// Slowest playback, time scales below are raised to this
+    pub const MIN_TIME_SCALE: f64 = 1.0 / 1024.0;
+    /// Fastest playback, time scales above are lowered to this
+    pub const MAX_TIME_SCALE: f64 = 1024.0;
+
+    /// Real time speed and at most a quarter second of simulated time per frame
+    ///
+    /// # Panics
+    /// If `step` is zero
+    pub fn new(step: Duration) -> Self {
+        assert!(!step.is_zero(), "step must not be zero");
+
+        let max_steps_per_frame = (0.25 / step.as_secs_f64()).ceil().max(1.0) as u32;
+
+        FixedTimestep {
+            step,
+            time_scale: 1.0,
+            max_steps_per_frame,
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    /// See [`set_time_scale`](Self::set_time_scale)
+    ///
+    /// # Panics
+    /// If `time_scale` is negative or not finite
+    pub fn with_time_scale(mut self, time_scale: f64) -> Self {
+        self.set_time_scale(time_scale);
+        self
+    }
+
+    pub fn with_max_steps_per_frame(mut self, max_steps_per_frame: u32) -> Self {
+        self.max_steps_per_frame = max_steps_per_frame;
+        self
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    /// Changes playback speed while running, the time already accumulated is kept.
+    /// `time_scale` is clamped between [`MIN_TIME_SCALE`](Self::MIN_TIME_SCALE) and [`MAX_TIME_SCALE`](Self::MAX_TIME_SCALE).
+    ///
+    /// # Panics
+    /// If `time_scale` is negative or not finite
+    pub fn set_time_scale(&mut self, time_scale: f64) {
+        assert!(
+            time_scale.is_finite() && time_scale >= 0.0,
+            "time_scale must be finite and not negative"
+        );
+        self.time_scale = time_scale.clamp(Self::MIN_TIME_SCALE, Self::MAX_TIME_SCALE);
+    }
+
+    pub fn max_steps_per_frame(&self) -> u32 {
+        self.max_steps_per_frame
+    }
+
+    /// Adds `elapsed` wall-clock time and takes out as many whole steps as fit, up to the limit
+    pub fn advance(&mut self, elapsed: Duration) -> FrameSteps {
+        self.accumulator += elapsed.mul_f64(self.time_scale);
+
+        let available = self.accumulator.as_nanos() / self.step.as_nanos();
+        let steps = available.min(self.max_steps_per_frame as u128) as u32;
+        self.accumulator -= self.step * steps;
+
+        let dropped = if (steps as u128) < available {
+            let kept =
+                Duration::from_nanos((self.accumulator.as_nanos() % self.step.as_nanos()) as u64);
+            let dropped = self.accumulator - kept;
+            self.accumulator = kept;
+            dropped
+        } else {
+            Duration::ZERO
+        };
+
+        FrameSteps { steps, dropped }
+    }
+
+    /// Fraction of a step that is accumulated but not stepped yet, between 0 and 1
+    pub fn alpha(&self) -> f64 {
+        self.accumulator.as_secs_f64() / self.step.as_secs_f64()
+    }
+}
+
+#[test]
+fn test_fixed_timestep() {
+    let mut timestep = FixedTimestep::new(Duration::from_millis(10));
+    assert_eq!(timestep.max_steps_per_frame(), 25);
+
+    let frame = timestep.advance(Duration::from_millis(25));
+    assert_eq!(frame.steps, 2);
+    assert_eq!(frame.dropped, Duration::ZERO);
+    assert!((timestep.alpha() - 0.5).abs() < 1e-12);
+
+    // The leftover half step completes the next one
+    assert_eq!(timestep.advance(Duration::from_millis(5)).steps, 1);
+    assert_eq!(timestep.alpha(), 0.0);
+
+    timestep.set_time_scale(0.5);
+    assert_eq!(timestep.advance(Duration::from_millis(30)).steps, 1);
+    assert!((timestep.alpha() - 0.5).abs() < 1e-12);
+
+    // A very slow frame doesn't ask for a second of catching up
+    timestep.set_time_scale(1.0);
+    let frame = timestep.advance(Duration::from_secs(1));
+    assert_eq!(frame.steps, 25);
+    assert_eq!(frame.dropped, Duration::from_millis(750));
+    assert!((timestep.alpha() - 0.5).abs() < 1e-12);
+
+    timestep.set_time_scale(1e300);
+    assert_eq!(timestep.time_scale(), FixedTimestep::MAX_TIME_SCALE);
+    assert_eq!(timestep.advance(Duration::from_secs(1)).steps, 25);
+    timestep.set_time_scale(0.0);
+    assert_eq!(timestep.time_scale(), FixedTimestep::MIN_TIME_SCALE);
+    assert!(std::panic::catch_unwind(
+        || FixedTimestep::new(Duration::from_millis(10)).with_time_scale(f64::NAN)
+    )
+    .is_err());
+    assert!(std::panic::catch_unwind(
+        || FixedTimestep::new(Duration::from_millis(10)).with_time_scale(-1.0)
+    )
+    .is_err());
+    assert!(std::panic::catch_unwind(|| FixedTimestep::new(Duration::ZERO)).is_err());
+}
@@ -1,10 +1,9 @@
 use crate::core::util::{hsva_to_rgba, Point};
-use crate::render::Renderer;
+use crate::render::{Renderable, Renderer};
 use itertools::Itertools;
 use sdl2::pixels::Color;
 use sdl2::rect::Point as SDL2Point;
 use sdl2::render::WindowCanvas;
-use crate::DoublePendulumCollection;
 
 pub struct SDL2Renderer(WindowCanvas);
 
@@ -15,10 +14,9 @@ impl SDL2Renderer {
 }
 
 impl Renderer for SDL2Renderer {
-    fn render_frame(&mut self, pendulums: &DoublePendulumCollection) -> Result<(), String> {
-        let configurations_len_f64 = pendulums.pendulum_configurations().len() as f64;
-        let pendulum_a = pendulums.pendulum_a();
-        let pendulum_b = pendulums.pendulum_b();
+    fn render_frame(&mut self, pendulums: &impl Renderable) -> Result<(), String> {
+        let member_positions = pendulums.member_positions();
+        let configurations_len_f64 = member_positions.len() as f64;
 
         let canvas = &mut self.0;
 
@@ -26,11 +24,11 @@ impl Renderer for SDL2Renderer {
         let (rel_x_max, rel_y_max) = (x_max / 2, y_max / 2);
         let minimum_rel_max = u32::min(rel_x_max, rel_y_max);
         let midpoint = SDL2Point::new(rel_x_max as i32, rel_y_max as i32);
-        let max_extension = pendulums.pendulum_a().length()
-            + pendulums.pendulum_b().length();
-        let conversion_constant = minimum_rel_max as f64 / max_extension;
+        let member_extensions = pendulums.member_extensions();
 
-        let convert_point = |point: Point| {
+        // Every member gets scaled so that it would just reach the edge when fully extended
+        let convert_point = |point: Point, extension: f64| {
+            let conversion_constant = minimum_rel_max as f64 / extension;
             SDL2Point::new(
                 (conversion_constant * point.x) as i32,
                 (conversion_constant * -point.y) as i32,
@@ -40,38 +38,38 @@ impl Renderer for SDL2Renderer {
         canvas.set_draw_color(Color::BLACK);
         canvas.clear();
 
-        #[derive(Copy, Clone)]
         struct PendulumRenderInfo {
-            a_point: SDL2Point,
-            b_point: SDL2Point,
+            /// The pivot followed by every bob
+            points: Vec<SDL2Point>,
             h: f64,
         }
 
-        let render_infos: Vec<_> = pendulums
-            .pendulum_configurations()
-            .iter()
+        let render_infos: Vec<_> = member_positions
+            .into_iter()
+            .zip(member_extensions)
             .enumerate()
-            .map(|(i, pendulum)| {
-                let (a_position, b_position) = pendulum.positions(pendulum_a, pendulum_b);
-                let (new_a, new_b) = (convert_point(a_position), convert_point(b_position));
+            .map(|(i, (positions, extension))| {
+                let points = std::iter::once(midpoint)
+                    .chain(
+                        positions
+                            .into_iter()
+                            .map(|position| convert_point(position, extension)),
+                    )
+                    .collect();
 
                 let h = (360 * i) as f64 / configurations_len_f64;
 
-                PendulumRenderInfo {
-                    a_point: new_a,
-                    b_point: new_b,
-                    h,
-                }
+                PendulumRenderInfo { points, h }
             })
             .collect();
 
-        for (info_1, info_2) in render_infos.iter().tuple_windows() {
+        for (info_1, _info_2) in render_infos.iter().tuple_windows() {
             canvas.set_draw_color(hsva_to_rgba(info_1.h, 1.0, 1.0, 0.01));
 
-            canvas.draw_lines([midpoint, info_1.a_point, info_1.b_point].as_ref())?;
+            canvas.draw_lines(info_1.points.as_ref())?;
 
             canvas.set_draw_color(Color::BLUE);
-            canvas.draw_points([info_1.a_point, info_1.b_point].as_ref())?;
+            canvas.draw_points(&info_1.points[1..])?;
         }
 
         canvas.present();